@@ -1,14 +1,18 @@
-use std::time::Instant;
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
 
 use log::debug;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
 use rand::Rng;
 use ratatui::{
     style::{Color, Style},
-    text::Line,
-    widgets::{Block, Borders, ScrollbarState},
+    widgets::{Block, Borders, ListState, ScrollbarState},
 };
-use tui_textarea::TextArea;
+use tui_textarea::{CursorMove, TextArea};
 
+use crate::bulk::BulkAction;
+use crate::db::{FlashCard, StudyStats};
 use crate::Args;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -16,9 +20,23 @@ pub enum State {
     Idling,
     ShowFlashcard,
     AddFlashcard,
-    DisplaySavedPopup,
+    DisplayMessagePopup,
     DisplayDeletePopup,
+    ListFlashcards,
+    ListPrompt,
+    DisplayBulkDeletePopup,
+    ShowStats,
 }
+
+///What the text typed in at the bottom of the list view is for
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ListPrompt {
+    Search,
+    Retag,
+    Move,
+    Export,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum Select {
     Prev,
@@ -35,11 +53,21 @@ pub struct App<'a> {
     pub vertical_scroll_state: ScrollbarState,
     pub vertical_scroll: usize,
     pub popup_time: Option<Instant>,
+    pub popup_message: String,
+    /// How long the popup stays up for
+    pub popup_millis: u128,
     pub current_flashcard_number: usize,
     pub current_flashcard_id: usize,
+    pub current_flash_favourite: bool,
+    /// The card on display has gone in the review log
+    pub current_flash_reviewed: bool,
+    /// Only study starred cards, for both navigation and the timer
+    pub favourites_only: bool,
     //TODO remove this?
     pub current_flash_text: String,
     pub current_flash_title: String,
+    /// Author, source and so on, shown under the card
+    pub current_flash_source: String,
     pub current_flash_body: String,
     pub flip_mode: bool,
     pub card_flipped: bool,
@@ -47,24 +75,219 @@ pub struct App<'a> {
     pub first_shown: bool,
     pub cards_displayed: usize,
     pub draw_mode: FlashCardMode,
+    pub random_strategy: RandomStrategy,
+    /// Offsets still to be drawn in shuffle bag mode
+    pub shuffle_bag: Vec<usize>,
+    pub timer: Timer,
     ///we want to send a visually signal eg a copy
     pub visual_flicker: bool,
     /// Record of flashcards that have been displayed
     pub flashcard_log: Vec<usize>,
     pub flashcard_history_index: usize,
+    /// Cards shown in the list view, filtered by list_query
+    pub card_list: Vec<FlashCard>,
+    pub list_state: ListState,
+    pub list_query: String,
+    /// Ids of the cards picked out for a bulk action
+    pub selected_cards: BTreeSet<usize>,
+    pub list_prompt: ListPrompt,
+    pub prompt_input: String,
+    /// Outcome of the last bulk action, shown in the list view
+    pub list_message: String,
+    /// Loaded when the stats screen is opened
+    pub stats: StudyStats,
 }
 
-#[derive(clap::ValueEnum, Debug, Clone)]
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FlashCardMode {
     Forward,
     Backward,
     Random,
 }
 
+impl FlashCardMode {
+    ///The mode after this one, round and round we go
+    pub fn next(self) -> Self {
+        match self {
+            FlashCardMode::Forward => FlashCardMode::Backward,
+            FlashCardMode::Backward => FlashCardMode::Random,
+            FlashCardMode::Random => FlashCardMode::Forward,
+        }
+    }
+}
+
+///How a random card gets picked
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomStrategy {
+    ///Any card but the current one, all equally likely
+    Uniform,
+    ///Favour cards shown least often and least recently, and with higher priority
+    Weighted,
+    ///Every card comes up once before any card repeats
+    Shuffle,
+}
+
+const SAVED_POPUP_MILLIS: u128 = 500;
+const MESSAGE_POPUP_MILLIS: u128 = 2000;
+
+///Seconds between cards when the timer is switched on without --timer
+pub const DEFAULT_TIMER_CYCLE: usize = 10;
+///How much +/- changes the reading speed by
+const WPM_STEP: usize = 20;
+
+///How long a card takes to read, for the reading time timer
+#[derive(Debug, Clone, Copy)]
+pub struct ReadingPace {
+    pub words_per_minute: usize,
+    pub min_secs: usize,
+    pub max_secs: usize,
+}
+
+impl ReadingPace {
+    pub fn new(words_per_minute: usize, min_secs: usize, max_secs: usize) -> Self {
+        let min_secs = min_secs.max(1);
+        Self {
+            words_per_minute: words_per_minute.max(1),
+            min_secs,
+            max_secs: max_secs.max(min_secs),
+        }
+    }
+
+    pub fn display_secs(&self, text: &str) -> usize {
+        let words = text.split_whitespace().count();
+        (words * 60)
+            .div_ceil(self.words_per_minute)
+            .clamp(self.min_secs, self.max_secs)
+    }
+}
+
+impl Default for ReadingPace {
+    fn default() -> Self {
+        Self::new(200, 3, 60)
+    }
+}
+
+///Flicks to the next card every next_card_cycle seconds while enabled,
+///or after the card's reading time when reading_time is set.
+///Pausing freezes the countdown where it is
+#[derive(Debug)]
 pub struct Timer {
     pub start: Instant,
     pub next_card_cycle: usize,
-    pub draw_mode: FlashCardMode,
+    pub enabled: bool,
+    pub paused_at: Option<Instant>,
+    pub reading_time: bool,
+    pub reading_pace: ReadingPace,
+    ///Seconds the current card gets in reading time mode, see fit_to_text
+    pub card_cycle: usize,
+}
+
+impl Timer {
+    pub fn new(next_card_cycle: usize, enabled: bool) -> Self {
+        Self {
+            start: Instant::now(),
+            next_card_cycle: next_card_cycle.max(1),
+            enabled,
+            paused_at: None,
+            reading_time: false,
+            reading_pace: ReadingPace::default(),
+            card_cycle: next_card_cycle.max(1),
+        }
+    }
+
+    pub fn with_reading_time(mut self, reading_pace: ReadingPace) -> Self {
+        self.reading_time = true;
+        self.reading_pace = reading_pace;
+        self
+    }
+
+    ///Work out how long the card with this text should be shown for
+    pub fn fit_to_text(&mut self, text: &str) {
+        self.card_cycle = self.reading_pace.display_secs(text);
+    }
+
+    ///Seconds until the next card, whichever mode we are in
+    pub fn cycle_secs(&self) -> usize {
+        if self.reading_time {
+            self.card_cycle
+        } else {
+            self.next_card_cycle
+        }
+    }
+
+    pub fn toggle_reading_time(&mut self) {
+        self.reading_time = !self.reading_time;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    ///Time counted towards the next card, not counting any pause
+    pub fn elapsed(&self) -> Duration {
+        match self.paused_at {
+            Some(paused_at) => paused_at.duration_since(self.start),
+            None => self.start.elapsed(),
+        }
+    }
+
+    pub fn cycle(&self) -> Duration {
+        Duration::from_secs(self.cycle_secs() as u64)
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.enabled && !self.is_paused() && self.elapsed() > self.cycle()
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.cycle().saturating_sub(self.elapsed())
+    }
+
+    ///How far along we are to the next card, between 0 and 1
+    pub fn progress(&self) -> f64 {
+        (self.elapsed().as_secs_f64() / self.cycle().as_secs_f64()).min(1.0)
+    }
+
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+        if self.paused_at.is_some() {
+            self.paused_at = Some(self.start);
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        match self.paused_at.take() {
+            //push the start forward by however long we were paused
+            Some(paused_at) => self.start += paused_at.elapsed(),
+            None => self.paused_at = Some(Instant::now()),
+        }
+    }
+
+    ///Switching back on starts a fresh countdown
+    pub fn toggle_enabled(&mut self) {
+        self.enabled = !self.enabled;
+        self.paused_at = None;
+        self.restart();
+    }
+
+    ///Longer cycle, or slower reading in reading time mode
+    pub fn lengthen(&mut self) {
+        if self.reading_time {
+            let wpm = &mut self.reading_pace.words_per_minute;
+            *wpm = wpm.saturating_sub(WPM_STEP).max(WPM_STEP);
+        } else {
+            self.next_card_cycle += 1;
+        }
+    }
+
+    ///Shorter cycle, or faster reading in reading time mode
+    pub fn shorten(&mut self) {
+        if self.reading_time {
+            self.reading_pace.words_per_minute += WPM_STEP;
+        } else if self.next_card_cycle > 1 {
+            self.next_card_cycle -= 1;
+        }
+    }
 }
 
 impl App<'_> {
@@ -78,24 +301,46 @@ impl App<'_> {
             vertical_scroll_state: ScrollbarState::default(),
             vertical_scroll: 0,
             popup_time: None,
+            popup_message: String::new(),
+            popup_millis: SAVED_POPUP_MILLIS,
             current_flashcard_number: 0,
             current_flashcard_id: 0,
+            current_flash_favourite: false,
+            current_flash_reviewed: false,
+            favourites_only: args.favourites,
             current_flash_text: String::new(),
             current_flash_title: String::new(),
+            current_flash_source: String::new(),
             current_flash_body: String::new(),
             flip_mode: args.flip_mode,
             card_flipped: false,
             total_cards: 0,
             first_shown: false,
             cards_displayed: 0,
-            draw_mode: if let Some(mode) = args.mode.clone() {
-                mode
+            draw_mode: args.mode.unwrap_or(FlashCardMode::Random),
+            random_strategy: args.random,
+            shuffle_bag: vec![],
+            timer: if args.reading_time {
+                Timer::new(args.timer.unwrap_or(DEFAULT_TIMER_CYCLE), true).with_reading_time(
+                    ReadingPace::new(args.wpm, args.min_display, args.max_display),
+                )
             } else {
-                FlashCardMode::Random
+                Timer::new(
+                    args.timer.unwrap_or(DEFAULT_TIMER_CYCLE),
+                    args.timer.is_some(),
+                )
             },
             visual_flicker: false,
             flashcard_log: vec![],
             flashcard_history_index: 0,
+            card_list: vec![],
+            list_state: ListState::default(),
+            list_query: String::new(),
+            selected_cards: BTreeSet::new(),
+            list_prompt: ListPrompt::Search,
+            prompt_input: String::new(),
+            list_message: String::new(),
+            stats: StudyStats::default(),
         }
     }
 
@@ -103,15 +348,21 @@ impl App<'_> {
         self.flashcard_log.len()
     }
     ///Sets up the app to show the saved popup
-    ///This can and should be generalized
     pub fn display_saved_popup(&mut self) {
-        self.set_state(State::DisplaySavedPopup);
-        let now = Instant::now();
-        self.popup_time = Some(now);
+        self.display_popup("Saved", SAVED_POPUP_MILLIS);
     }
 
-    pub fn display_confirm_delete_popup(&mut self) {
-        self.set_state(State::DisplayDeletePopup);
+    ///Popup for anything that needs reading, so it hangs around longer
+    pub fn display_message_popup(&mut self, msg: &str) {
+        self.display_popup(msg, MESSAGE_POPUP_MILLIS);
+    }
+
+    fn display_popup(&mut self, msg: &str, millis: u128) {
+        self.set_state(State::DisplayMessagePopup);
+        self.popup_message = msg.to_string();
+        self.popup_millis = millis;
+        let now = Instant::now();
+        self.popup_time = Some(now);
     }
 
     pub fn close_popup_if_it_is_time(&mut self, time: u128) {
@@ -141,6 +392,15 @@ impl App<'_> {
         self.set_state(State::AddFlashcard)
     }
 
+    ///The editor, already holding this text, with the cursor on the title line
+    pub fn show_add_flashcard_with(&mut self, text: &str) {
+        self.input_area = init_input_area();
+        self.input_area.insert_str(text);
+        self.input_area.move_cursor(CursorMove::Top);
+        self.input_area.move_cursor(CursorMove::Head);
+        self.show_add_flashcard();
+    }
+
     pub fn stop_running(&mut self) {
         self.running = false;
     }
@@ -150,10 +410,6 @@ impl App<'_> {
         self.first_shown = false;
     }
 
-    pub fn update_flash_text(&mut self, flash_text: &str) {
-        self.current_flash_text = flash_text.to_string();
-    }
-
     ///Reset the scrollbar state based on self.current_flash_text
     pub fn reset_scrollbar_state(&mut self) {
         //reset to the beginning bebe
@@ -188,6 +444,9 @@ impl App<'_> {
     }
 
     pub fn increment_flash_count(&mut self) {
+        if self.total_cards == 0 {
+            return;
+        }
         if self.at_end_of_history() {
             // At end of history: calculate next sequential card and add to log
             let next = (self.current_flashcard_number + 1) % self.total_cards;
@@ -237,6 +496,55 @@ impl App<'_> {
             }
         }
     }
+    ///Pick a card with probability proportional to its weight,
+    ///weights being in offset order, see db::fetch_card_weights
+    pub fn pick_weighted_flash_count(&mut self, weights: &[f64]) {
+        if self.total_cards <= 1 {
+            return;
+        }
+        let mut weights = weights.to_vec();
+        weights.truncate(self.total_cards);
+        //no repeats
+        if let Some(w) = weights.get_mut(self.current_flashcard_number) {
+            *w = 0.0;
+        }
+        match WeightedIndex::new(&weights) {
+            Ok(dist) => {
+                let candidate = dist.sample(&mut rand::thread_rng());
+                self.current_flashcard_number = candidate;
+                self.push_to_log(candidate);
+            }
+            //all zero or out of whack, so just go uniform
+            Err(e) => {
+                debug!("Could not weight the cards: {}", e);
+                self.randomize_flash_count();
+            }
+        }
+    }
+
+    ///Draw the next card from the bag, refilling it with every card once it is empty
+    pub fn draw_from_shuffle_bag(&mut self) {
+        if self.total_cards <= 1 {
+            return;
+        }
+        //cards may have been deleted since the bag was filled
+        let total = self.total_cards;
+        self.shuffle_bag.retain(|&i| i < total);
+        if self.shuffle_bag.is_empty() {
+            let mut bag = (0..total).collect::<Vec<_>>();
+            bag.shuffle(&mut rand::thread_rng());
+            //we pop off the end, so keep the current card from coming straight back
+            if bag.last() == Some(&self.current_flashcard_number) {
+                bag.swap(0, total - 1);
+            }
+            self.shuffle_bag = bag;
+        }
+        if let Some(next) = self.shuffle_bag.pop() {
+            self.current_flashcard_number = next;
+            self.push_to_log(next);
+        }
+    }
+
     pub fn show_flash_card(&mut self) {
         self.set_state(State::ShowFlashcard);
     }
@@ -254,17 +562,6 @@ impl App<'_> {
         self.set_state(State::ShowFlashcard);
     }
 
-    ///A vec of lines for the current flashcard
-    pub fn text_lines<'a>(&'a self) -> Vec<Line<'a>> {
-        let mut full_text = Vec::new();
-
-        for line in self.current_flash_text.split('\n') {
-            let ln = Line::from(line);
-            full_text.push(ln);
-        }
-        full_text
-    }
-
     pub fn scroll_down(&mut self) {
         self.vertical_scroll += 1;
         self.vertical_scroll_state = self.vertical_scroll_state.position(self.vertical_scroll);
@@ -277,6 +574,15 @@ impl App<'_> {
         self.vertical_scroll_state = self.vertical_scroll_state.position(self.vertical_scroll);
     }
 
+    ///Only flick through cards while looking at them, not while editing or listing
+    pub fn timer_can_advance(&self) -> bool {
+        matches!(self.state, State::Idling | State::ShowFlashcard) && self.timer.is_due()
+    }
+
+    pub fn cycle_draw_mode(&mut self) {
+        self.draw_mode = self.draw_mode.next();
+    }
+
     pub fn has_flashcards(&self) -> bool {
         self.total_cards > 0
     }
@@ -284,6 +590,126 @@ impl App<'_> {
     pub fn start_delete(&mut self) {
         self.set_state(State::DisplayDeletePopup);
     }
+
+    ///Swap in a freshly loaded list of cards, keeping the highlight in bounds
+    ///and forgetting selections for cards that are no longer listed
+    pub fn load_card_list(&mut self, cards: Vec<FlashCard>) {
+        self.card_list = cards;
+        let listed = self
+            .card_list
+            .iter()
+            .map(|fc| fc.id)
+            .collect::<BTreeSet<_>>();
+        self.selected_cards.retain(|id| listed.contains(id));
+        let highlight = match self.list_state.selected() {
+            _ if self.card_list.is_empty() => None,
+            Some(i) => Some(i.min(self.card_list.len() - 1)),
+            None => Some(0),
+        };
+        self.list_state.select(highlight);
+    }
+
+    pub fn show_stats(&mut self, stats: StudyStats) {
+        self.stats = stats;
+        self.set_state(State::ShowStats);
+    }
+
+    pub fn show_card_list(&mut self) {
+        self.set_state(State::ListFlashcards);
+    }
+
+    pub fn list_down(&mut self) {
+        if !self.card_list.is_empty() {
+            let next = self
+                .list_state
+                .selected()
+                .map_or(0, |i| (i + 1).min(self.card_list.len() - 1));
+            self.list_state.select(Some(next));
+        }
+    }
+
+    pub fn list_up(&mut self) {
+        if let Some(i) = self.list_state.selected() {
+            self.list_state.select(Some(i.saturating_sub(1)));
+        }
+    }
+
+    pub fn highlighted_card(&self) -> Option<&FlashCard> {
+        self.list_state
+            .selected()
+            .and_then(|i| self.card_list.get(i))
+    }
+
+    pub fn toggle_highlighted_selection(&mut self) {
+        if let Some(id) = self.highlighted_card().map(|fc| fc.id) {
+            if !self.selected_cards.remove(&id) {
+                self.selected_cards.insert(id);
+            }
+        }
+    }
+
+    ///Select everything listed, or clear the selection if that is already the case
+    pub fn toggle_select_all(&mut self) {
+        if self.selected_cards.len() == self.card_list.len() {
+            self.selected_cards.clear();
+        } else {
+            self.selected_cards = self.card_list.iter().map(|fc| fc.id).collect();
+        }
+    }
+
+    ///Select every listed card sharing the highlighted card's title
+    pub fn select_same_title(&mut self) {
+        if let Some(title) = self.highlighted_card().map(|fc| fc.title.clone()) {
+            self.card_list
+                .iter()
+                .filter(|fc| fc.title == title)
+                .for_each(|fc| {
+                    self.selected_cards.insert(fc.id);
+                });
+        }
+    }
+
+    pub fn selected_flashcards(&self) -> Vec<FlashCard> {
+        self.card_list
+            .iter()
+            .filter(|fc| self.selected_cards.contains(&fc.id))
+            .cloned()
+            .collect()
+    }
+
+    pub fn start_list_prompt(&mut self, prompt: ListPrompt) {
+        self.list_prompt = prompt;
+        self.prompt_input = match prompt {
+            ListPrompt::Search => self.list_query.clone(),
+            _ => String::new(),
+        };
+        self.set_state(State::ListPrompt);
+    }
+
+    ///The action the prompt was asking about, None when it was a search
+    pub fn prompted_bulk_action(&self) -> Option<BulkAction> {
+        let input = self.prompt_input.trim().to_string();
+        match self.list_prompt {
+            ListPrompt::Search => None,
+            ListPrompt::Retag => Some(BulkAction::Retag(input)),
+            ListPrompt::Move => Some(BulkAction::Move(input)),
+            ListPrompt::Export => Some(BulkAction::Export(input)),
+        }
+    }
+
+    pub fn start_bulk_delete(&mut self) {
+        if !self.selected_cards.is_empty() {
+            self.set_state(State::DisplayBulkDeletePopup);
+        }
+    }
+
+    ///Offsets in the history may point at cards which no longer exist
+    pub fn forget_history(&mut self) {
+        self.flashcard_log.clear();
+        self.flashcard_history_index = 0;
+        self.first_shown = false;
+        self.reset_count();
+    }
 }
 
 pub fn init_input_area<'a>() -> TextArea<'a> {
@@ -307,10 +733,16 @@ impl Default for App<'_> {
             vertical_scroll_state: ScrollbarState::default(),
             vertical_scroll: 0,
             popup_time: None,
+            popup_message: String::new(),
+            popup_millis: SAVED_POPUP_MILLIS,
             current_flashcard_number: 0,
             current_flashcard_id: 0,
+            current_flash_favourite: false,
+            current_flash_reviewed: false,
+            favourites_only: false,
             current_flash_text: String::new(),
             current_flash_title: String::new(),
+            current_flash_source: String::new(),
             current_flash_body: String::new(),
             flip_mode: false,
             card_flipped: false,
@@ -318,9 +750,20 @@ impl Default for App<'_> {
             first_shown: false,
             cards_displayed: 0,
             draw_mode: FlashCardMode::Random,
+            random_strategy: RandomStrategy::Uniform,
+            shuffle_bag: vec![],
+            timer: Timer::new(DEFAULT_TIMER_CYCLE, false),
             visual_flicker: false,
             flashcard_log: vec![],
             flashcard_history_index: 0,
+            card_list: vec![],
+            list_state: ListState::default(),
+            list_query: String::new(),
+            selected_cards: BTreeSet::new(),
+            list_prompt: ListPrompt::Search,
+            prompt_input: String::new(),
+            list_message: String::new(),
+            stats: StudyStats::default(),
         }
     }
 }
@@ -345,4 +788,44 @@ mod test {
         //     res
         // );
     }
+
+    #[test]
+    pub fn test_timer_pause_freezes_countdown() {
+        let mut timer = Timer::new(5, true);
+        timer.start -= Duration::from_secs(3);
+        timer.toggle_pause();
+        assert!(timer.is_paused());
+        let frozen = timer.remaining();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(frozen, timer.remaining());
+        timer.toggle_pause();
+        assert!(!timer.is_paused());
+        assert!(timer.remaining() <= Duration::from_secs(2));
+        (0..10).for_each(|_| timer.shorten());
+        assert_eq!(1, timer.next_card_cycle);
+    }
+
+    #[test]
+    pub fn test_shuffle_bag_shows_every_card_before_repeating() {
+        let mut app = App {
+            total_cards: 7,
+            ..Default::default()
+        };
+        let mut seen = (0..7)
+            .map(|_| {
+                app.draw_from_shuffle_bag();
+                app.current_flashcard_number
+            })
+            .collect::<Vec<_>>();
+        seen.sort();
+        assert_eq!((0..7).collect::<Vec<_>>(), seen);
+    }
+
+    #[test]
+    pub fn test_reading_pace_bounds() {
+        let pace = ReadingPace::new(120, 3, 20);
+        assert_eq!(3, pace.display_secs("Know thyself"));
+        assert_eq!(10, pace.display_secs(&"word ".repeat(20)));
+        assert_eq!(20, pace.display_secs(&"word ".repeat(400)));
+    }
 }
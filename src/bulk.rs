@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::io::{stdin, stdout, Write};
+
+use anyhow::Result;
+use colored::Colorize;
+use rusqlite::Connection;
+
+use crate::db::{self, CardSelection, FlashCard};
+use crate::export::export_flashcards;
+
+///Something we do to a whole bunch of cards at once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BulkAction {
+    Delete,
+    Retag(String),
+    Move(String),
+    Export(String),
+    Prioritise(usize),
+}
+
+impl BulkAction {
+    pub fn describe(&self) -> String {
+        match self {
+            BulkAction::Delete => "delete".to_string(),
+            BulkAction::Retag(tags) => format!("retag with '{}'", db::normalise_tags(tags)),
+            BulkAction::Move(deck) => format!("move to deck '{}'", deck.trim()),
+            BulkAction::Export(fp) => format!("export to {}", fp),
+            BulkAction::Prioritise(priority) => format!("set priority {} on", priority),
+        }
+    }
+}
+
+///How the command line picks out cards, at least one of these is needed
+#[derive(clap::Args, Debug, Clone)]
+#[group(required = true, multiple = true)]
+pub struct SelectionArgs {
+    ///Cards whose title, body or author contain this text
+    #[arg(short, long)]
+    pub query: Option<String>,
+    ///Cards whose title contains this text
+    #[arg(long)]
+    pub title: Option<String>,
+    ///Card id, or inclusive range of ids, eg 10-20
+    #[arg(long, value_parser = parse_id_range)]
+    pub ids: Option<(usize, usize)>,
+}
+
+impl From<SelectionArgs> for CardSelection {
+    fn from(args: SelectionArgs) -> Self {
+        Self {
+            query: args.query,
+            title: args.title,
+            ids: args.ids,
+        }
+    }
+}
+
+pub fn parse_id_range(s: &str) -> Result<(usize, usize), String> {
+    let parse = |part: &str| {
+        part.trim()
+            .parse::<usize>()
+            .map_err(|e| format!("'{}' is not an id: {}", part, e))
+    };
+    match s.split_once('-') {
+        Some((from, to)) => {
+            let (from, to) = (parse(from)?, parse(to)?);
+            if from > to {
+                return Err(format!("{} is bigger than {}", from, to));
+            }
+            Ok((from, to))
+        }
+        None => {
+            let id = parse(s)?;
+            Ok((id, id))
+        }
+    }
+}
+
+///Command line entry point: find the cards, show what we are about to do,
+///ask unless told not to, then do it
+pub fn run_bulk_action(
+    action: &BulkAction,
+    selection: &CardSelection,
+    assume_yes: bool,
+    conn: &Connection,
+) -> Result<()> {
+    let cards = db::find_flashcards(selection, conn)?;
+    if cards.is_empty() {
+        println!("{}", "No flashcards matched".yellow());
+        return Ok(());
+    }
+    print_summary(action, &cards);
+    if !assume_yes && !confirm("Proceed?")? {
+        println!("{}", "Nothing changed".yellow());
+        return Ok(());
+    }
+    let changed = apply_bulk_action(action, &cards, conn)?;
+    println!(
+        "{} {} flashcards",
+        "Done:".green(),
+        changed.to_string().purple()
+    );
+    Ok(())
+}
+
+///Shared by the command line and the list view
+pub fn apply_bulk_action(
+    action: &BulkAction,
+    cards: &[FlashCard],
+    conn: &Connection,
+) -> Result<usize> {
+    let ids = cards.iter().map(|fc| fc.id).collect::<Vec<_>>();
+    match action {
+        BulkAction::Delete => db::delete_flashcards(&ids, conn),
+        BulkAction::Retag(tags) => db::retag_flashcards(&ids, tags, conn),
+        BulkAction::Move(deck) => db::move_flashcards_to_deck(&ids, deck, conn),
+        BulkAction::Prioritise(priority) => db::prioritise_flashcards(&ids, *priority, conn),
+        BulkAction::Export(fp) => {
+            export_flashcards(cards, fp)?;
+            Ok(cards.len())
+        }
+    }
+}
+
+fn print_summary(action: &BulkAction, cards: &[FlashCard]) {
+    let first = cards.first().map(|fc| fc.id).unwrap_or_default();
+    let last = cards.last().map(|fc| fc.id).unwrap_or_default();
+    println!(
+        "About to {} {} flashcards (ids {} to {})",
+        action.describe().red(),
+        cards.len().to_string().purple(),
+        first,
+        last
+    );
+    let mut per_title: BTreeMap<String, usize> = BTreeMap::new();
+    for fc in cards {
+        *per_title
+            .entry(fc.title.trim().replace('\n', " - "))
+            .or_default() += 1;
+    }
+    per_title.iter().for_each(|(title, count)| {
+        println!("  {} -> {}", title.cyan(), count.to_string().purple());
+    });
+}
+
+///Ask a yes or no question, anything but yes is no
+pub fn confirm(question: &str) -> Result<bool> {
+    let answer = prompt_line(&format!("{} [y/N]", question))?;
+    Ok(matches!(answer.as_str(), "y" | "Y" | "yes"))
+}
+
+///Ask for a line of input, trimmed
+pub fn prompt_line(question: &str) -> Result<String> {
+    print!("{} ", question);
+    stdout().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::parse_id_range;
+
+    #[test]
+    pub fn test_parse_id_range() {
+        assert_eq!(Ok((7, 7)), parse_id_range("7"));
+        assert_eq!(Ok((10, 20)), parse_id_range("10-20"));
+        assert!(parse_id_range("20-10").is_err());
+        assert!(parse_id_range("ten").is_err());
+    }
+}
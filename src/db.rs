@@ -1,15 +1,103 @@
-use std::collections::HashMap;
-
 use anyhow::{bail, Result};
 use log::info;
-use rusqlite::{params, Connection};
+use rusqlite::{params, params_from_iter, types::Value, Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+///Columns selected whenever we build a full FlashCard from a row, see flashcard_from_row
+const FLASHCARD_COLUMNS: &str = "id, title, body, tags, deck, favourite, source_title, author, \
+     location, source_url, note, highlighted_at, metadata";
 
-#[derive(Debug)]
+///Missing fields are left empty when reading one in, as plugins do
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct FlashCard {
     pub title: String,
     pub body: String,
     //db id
     pub id: usize,
+    ///Comma separated, see normalise_tags
+    pub tags: String,
+    pub deck: String,
+    pub favourite: bool,
+    ///Book, article, etc the card was taken from
+    pub source_title: String,
+    pub author: String,
+    ///Page, chapter, percentage, whatever the source uses
+    pub location: String,
+    pub source_url: String,
+    ///The reader's own note on the highlight
+    pub note: String,
+    ///When the highlight was made, as the source wrote it
+    pub highlighted_at: String,
+    ///Anything else the source told us, kept so exports can give it back.
+    ///Stored as a JSON object
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl FlashCard {
+    ///A card typed or pasted in: the first line is the title, the rest the body,
+    ///the same as the editor saves them
+    pub fn from_text(text: &str) -> Self {
+        let text = text.trim_start();
+        let (title, body) = text.split_once('\n').unwrap_or((text, ""));
+        Self {
+            title: title.to_string(),
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    ///Where the card came from, on one line, empty if we don't know
+    pub fn source_line(&self) -> String {
+        [
+            &self.author,
+            &self.source_title,
+            &self.location,
+            &self.source_url,
+        ]
+        .iter()
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+///What weighted random selection needs to know about a card
+pub struct CardWeight {
+    pub view_count: usize,
+    ///None if the card has never been shown
+    pub secs_since_viewed: Option<i64>,
+    pub priority: usize,
+    pub favourite: bool,
+}
+
+///Cards not seen for this long are all as overdue as each other
+const MAX_OVERDUE_SECS: i64 = 7 * 24 * 60 * 60;
+
+impl CardWeight {
+    ///Higher for cards that are important, seen rarely, and not seen lately
+    pub fn weight(&self) -> f64 {
+        let overdue = self
+            .secs_since_viewed
+            .map_or(MAX_OVERDUE_SECS, |secs| secs.clamp(0, MAX_OVERDUE_SECS));
+        let hours = overdue as f64 / 3600.0;
+        let star = if self.favourite { 2.0 } else { 1.0 };
+        star * self.priority as f64 * (1.0 + hours) / (1.0 + self.view_count as f64)
+    }
+}
+
+///Which cards a bulk operation should touch.
+///Every filter that is set must match
+#[derive(Debug, Default, Clone)]
+pub struct CardSelection {
+    ///Matched against title, body and author
+    pub query: Option<String>,
+    pub title: Option<String>,
+    ///Inclusive range of db ids
+    pub ids: Option<(usize, usize)>,
 }
 
 ///Titles are book titles
@@ -18,11 +106,34 @@ pub struct CardTitleReport {
     pub report_lines: Vec<ReportLine>,
 }
 
+#[derive(Serialize)]
 pub struct ReportLine {
     pub title: String,
     pub title_count: usize,
 }
 
+///How many cards share something, eg an author or the month they were added
+#[derive(Serialize)]
+pub struct CountLine {
+    pub name: String,
+    pub count: usize,
+}
+
+#[derive(Serialize)]
+pub struct CardViews {
+    pub id: usize,
+    pub title: String,
+    pub view_count: usize,
+}
+
+///A card that is probably junk, and why we think so
+#[derive(Serialize)]
+pub struct ProblemCard {
+    pub id: usize,
+    pub title: String,
+    pub problem: String,
+}
+
 impl CardTitleReport {
     pub fn new() -> Self {
         Self {
@@ -37,9 +148,17 @@ impl CardTitleReport {
 
 pub fn default_connection() -> Result<Connection> {
     let conn = Connection::open("./.rashcard.db")?;
+    //the TUI and `import --watch` can have the database open at the same time
+    conn.busy_timeout(Duration::from_secs(5))?;
     Ok(conn)
 }
 
+///Changes whenever another connection commits, so we know to count the cards again
+pub fn data_version(conn: &Connection) -> Result<i64> {
+    let version = conn.query_row("PRAGMA data_version", [], |row| row.get(0))?;
+    Ok(version)
+}
+
 pub fn init_table(conn: &Connection) -> Result<()> {
     conn.execute(
         r"CREATE TABLE IF NOT EXISTS flashcard 
@@ -50,11 +169,145 @@ pub fn init_table(conn: &Connection) -> Result<()> {
                   last_update TEXT DEFAULT CURRENT_TIMESTAMP)",
         [],
     )?;
+    //older databases were created without these
+    add_column_if_missing(conn, "flashcard", "tags", "TEXT NOT NULL DEFAULT ''")?;
+    add_column_if_missing(conn, "flashcard", "deck", "TEXT NOT NULL DEFAULT ''")?;
+    add_column_if_missing(
+        conn,
+        "flashcard",
+        "view_count",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    add_column_if_missing(conn, "flashcard", "last_viewed", "TEXT")?;
+    add_column_if_missing(conn, "flashcard", "priority", "INTEGER NOT NULL DEFAULT 1")?;
+    add_column_if_missing(conn, "flashcard", "favourite", "INTEGER NOT NULL DEFAULT 0")?;
+    for column in [
+        "source_title",
+        "author",
+        "location",
+        "source_url",
+        "note",
+        "highlighted_at",
+    ] {
+        add_column_if_missing(conn, "flashcard", column, "TEXT NOT NULL DEFAULT ''")?;
+    }
+    add_column_if_missing(conn, "flashcard", "metadata", "TEXT NOT NULL DEFAULT '{}'")?;
+    split_title_blobs(conn)?;
+    conn.execute(
+        r"CREATE TABLE IF NOT EXISTS review_log
+                 (id INTEGER PRIMARY KEY,
+                  card_id INTEGER NOT NULL,
+                  reviewed_at TEXT DEFAULT CURRENT_TIMESTAMP,
+                  grade INTEGER)",
+        [],
+    )?;
+    conn.execute(
+        r"CREATE TABLE IF NOT EXISTS import_source
+                 (id INTEGER PRIMARY KEY,
+                  path TEXT NOT NULL,
+                  content_hash TEXT NOT NULL,
+                  card_count INTEGER NOT NULL,
+                  imported_at TEXT DEFAULT CURRENT_TIMESTAMP,
+                  UNIQUE(path, content_hash))",
+        [],
+    )?;
+    Ok(())
+}
+
+///Importers used to glue the author onto the title with a newline,
+///pull those apart into title, source_title and author
+fn split_title_blobs(conn: &Connection) -> Result<()> {
+    let blobs = {
+        let mut qry = conn.prepare(
+            "SELECT id, title FROM flashcard
+             WHERE instr(title, char(10)) > 0 AND source_title = '' AND author = ''",
+        )?;
+        let rows = qry
+            .query_map([], |row| {
+                Ok((row.get::<_, usize>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows
+    };
+    if blobs.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "Splitting {} title blobs into title and author",
+        blobs.len()
+    );
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(
+            "UPDATE flashcard SET title = ?1, source_title = ?1, author = ?2 WHERE id = ?3",
+        )?;
+        for (id, blob) in blobs {
+            let mut lines = blob.lines().map(str::trim).filter(|line| !line.is_empty());
+            let title = lines.next().unwrap_or_default();
+            let author = lines.next().unwrap_or_default();
+            stmt.execute(params![title, author, id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+///SQLite has no ADD COLUMN IF NOT EXISTS, so we check the table info ourselves
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+    if !exists {
+        info!("Adding column {} to {}", column, table);
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
     Ok(())
 }
 
-pub fn fetch_initial_flash_card_count(conn: &Connection) -> Result<usize> {
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM flashcard")?;
+fn flashcard_from_row(row: &Row) -> rusqlite::Result<FlashCard> {
+    Ok(FlashCard {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        body: row.get(2)?,
+        tags: row.get(3)?,
+        deck: row.get(4)?,
+        favourite: row.get(5)?,
+        source_title: row.get(6)?,
+        author: row.get(7)?,
+        location: row.get(8)?,
+        source_url: row.get(9)?,
+        note: row.get(10)?,
+        highlighted_at: row.get(11)?,
+        //hand edited rows shouldn't stop the card from loading
+        metadata: serde_json::from_str(&row.get::<_, String>(12)?).unwrap_or_default(),
+    })
+}
+
+///Narrows down the cards we study, offsets and counts are within what is left
+fn study_filter(favourites_only: bool) -> &'static str {
+    if favourites_only {
+        "WHERE favourite = 1"
+    } else {
+        ""
+    }
+}
+
+pub fn fetch_initial_flash_card_count(favourites_only: bool, conn: &Connection) -> Result<usize> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT COUNT(*) FROM flashcard {}",
+        study_filter(favourites_only)
+    ))?;
     let mut rows = stmt.query([])?;
     let mut count = 0;
     if let Some(row) = rows.next()? {
@@ -63,30 +316,59 @@ pub fn fetch_initial_flash_card_count(conn: &Connection) -> Result<usize> {
     Ok(count)
 }
 
-pub fn save_flashcard_object(fc: &FlashCard, conn: &Connection) -> Result<()> {
-    save_flashcard(&fc.title, &fc.body, conn)
+///Save a new card, giving back its id
+pub fn save_flashcard_object(fc: &FlashCard, conn: &Connection) -> Result<usize> {
+    FlashCardInserter::new(conn)?.insert(fc)
 }
 
-pub fn save_flashcard(title: &str, body: &str, conn: &Connection) -> Result<()> {
-    conn.execute(
-        "INSERT INTO flashcard(title, body) values (?1, ?2)",
-        [title, body],
-    )?;
+///Prepares the insert once so big batches don't pay for it on every card,
+///use inside a transaction
+pub struct FlashCardInserter<'conn> {
+    stmt: rusqlite::Statement<'conn>,
+}
 
-    Ok(())
+impl<'conn> FlashCardInserter<'conn> {
+    pub fn new(conn: &'conn Connection) -> Result<Self> {
+        let stmt = conn.prepare(
+            "INSERT INTO flashcard(title, body, tags, deck, source_title, author, location, source_url,
+                                   note, highlighted_at, metadata, favourite)
+             values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        )?;
+        Ok(Self { stmt })
+    }
+
+    ///Insert the card and give back its id
+    pub fn insert(&mut self, fc: &FlashCard) -> Result<usize> {
+        let id = self.stmt.insert(params![
+            fc.title,
+            fc.body,
+            normalise_tags(&fc.tags),
+            fc.deck,
+            fc.source_title,
+            fc.author,
+            fc.location,
+            fc.source_url,
+            fc.note,
+            fc.highlighted_at,
+            serde_json::to_string(&fc.metadata)?,
+            fc.favourite,
+        ])?;
+        Ok(id as usize)
+    }
 }
 
-pub fn next_flashcard(offset: usize, conn: &Connection) -> Result<Option<FlashCard>> {
+pub fn next_flashcard(
+    offset: usize,
+    favourites_only: bool,
+    conn: &Connection,
+) -> Result<Option<FlashCard>> {
     info!("This is the offset for next flashcard: {}", offset);
-    let mut qry =
-        conn.prepare("SELECT id, title, body FROM flashcard ORDER BY id LIMIT 1 OFFSET ?")?;
-    let flashcards = qry.query_map(params![offset], |row| {
-        Ok(FlashCard {
-            id: row.get(0)?,
-            title: row.get(1)?,
-            body: row.get(2)?,
-        })
-    })?;
+    let mut qry = conn.prepare(&format!(
+        "SELECT {} FROM flashcard {} ORDER BY id LIMIT 1 OFFSET ?",
+        FLASHCARD_COLUMNS,
+        study_filter(favourites_only)
+    ))?;
+    let flashcards = qry.query_map(params![offset], flashcard_from_row)?;
 
     let mut flashcard = None;
     //should only be one in here
@@ -101,11 +383,277 @@ pub fn next_flashcard(offset: usize, conn: &Connection) -> Result<Option<FlashCa
     Ok(flashcard)
 }
 
+///Is there already a card with exactly this title and body
+pub fn flashcard_exists(title: &str, body: &str, conn: &Connection) -> Result<bool> {
+    let exists = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM flashcard WHERE title = ?1 AND body = ?2)",
+        params![title, body],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+///Has this file, with exactly these contents, been imported before.
+///A hash without importer options, see `import::source_hash`, also
+///matches the same contents imported with any options
+pub fn import_source_exists(path: &str, content_hash: &str, conn: &Connection) -> Result<bool> {
+    let exists = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM import_source WHERE path = ?1
+         AND (content_hash = ?2 OR content_hash LIKE ?2 || '+%'))",
+        params![path, content_hash],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+///Has anything been imported from this file before, whatever it held then
+pub fn import_source_path_exists(path: &str, conn: &Connection) -> Result<bool> {
+    let exists = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM import_source WHERE path = ?1)",
+        params![path],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+pub fn record_import_source(
+    path: &str,
+    content_hash: &str,
+    card_count: usize,
+    conn: &Connection,
+) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO import_source(path, content_hash, card_count) VALUES (?1, ?2, ?3)",
+        params![path, content_hash, card_count],
+    )?;
+    Ok(())
+}
+
+///Keep track of how often and when a card has been shown
+pub fn record_view(fc_id: usize, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "UPDATE flashcard SET view_count = view_count + 1, last_viewed = CURRENT_TIMESTAMP WHERE id = ?1",
+        params![fc_id],
+    )?;
+    Ok(())
+}
+
+///Log that the card was studied, for the stats screen. Only for cards the user
+///went to themselves, the timer flicking through while they're away doesn't count
+pub fn record_review(fc_id: usize, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT INTO review_log(card_id) VALUES (?1)",
+        params![fc_id],
+    )?;
+    Ok(())
+}
+
+///Mark the latest review of the card as remembered or not
+pub fn grade_latest_review(fc_id: usize, correct: bool, conn: &Connection) -> Result<()> {
+    conn.execute(
+        "UPDATE review_log SET grade = ?1
+         WHERE id = (SELECT MAX(id) FROM review_log WHERE card_id = ?2)",
+        params![correct, fc_id],
+    )?;
+    Ok(())
+}
+
+///Weights for every card, in the same order as the offsets used by next_flashcard
+pub fn fetch_card_weights(favourites_only: bool, conn: &Connection) -> Result<Vec<CardWeight>> {
+    let mut qry = conn.prepare(&format!(
+        "SELECT view_count, priority, favourite,
+                CAST(strftime('%s', 'now') AS INTEGER) - CAST(strftime('%s', last_viewed) AS INTEGER)
+         FROM flashcard {} ORDER BY id",
+        study_filter(favourites_only)
+    ))?;
+    let weights = qry
+        .query_map([], |row| {
+            Ok(CardWeight {
+                view_count: row.get(0)?,
+                priority: row.get(1)?,
+                favourite: row.get(2)?,
+                secs_since_viewed: row.get(3)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(weights)
+}
+
+///Star or unstar a card, returns the new state
+pub fn toggle_favourite(fc_id: usize, conn: &Connection) -> Result<bool> {
+    conn.execute(
+        "UPDATE flashcard SET favourite = NOT favourite, last_update = CURRENT_TIMESTAMP WHERE id = ?1",
+        params![fc_id],
+    )?;
+    let favourite = conn.query_row(
+        "SELECT favourite FROM flashcard WHERE id = ?1",
+        params![fc_id],
+        |row| row.get(0),
+    )?;
+    Ok(favourite)
+}
+
+pub fn fetch_favourites(conn: &Connection) -> Result<Vec<FlashCard>> {
+    let mut qry = conn.prepare(&format!(
+        "SELECT {} FROM flashcard {} ORDER BY title, id",
+        FLASHCARD_COLUMNS,
+        study_filter(true)
+    ))?;
+    let cards = qry
+        .query_map([], flashcard_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(cards)
+}
+
+pub fn fetch_flashcard(fc_id: usize, conn: &Connection) -> Result<Option<FlashCard>> {
+    let mut qry = conn.prepare(&format!(
+        "SELECT {} FROM flashcard WHERE id = ?1",
+        FLASHCARD_COLUMNS
+    ))?;
+    let fc = qry
+        .query_map(params![fc_id], flashcard_from_row)?
+        .next()
+        .transpose()?;
+    Ok(fc)
+}
+
+///Write every field of the card over the row with its id, false if there is no such row
+pub fn update_flashcard(fc: &FlashCard, conn: &Connection) -> Result<bool> {
+    let updated = conn.execute(
+        "UPDATE flashcard SET title = ?1, body = ?2, tags = ?3, deck = ?4, favourite = ?5,
+                              source_title = ?6, author = ?7, location = ?8, source_url = ?9,
+                              note = ?10, highlighted_at = ?11, metadata = ?12,
+                              last_update = CURRENT_TIMESTAMP
+         WHERE id = ?13",
+        params![
+            fc.title,
+            fc.body,
+            normalise_tags(&fc.tags),
+            fc.deck,
+            fc.favourite,
+            fc.source_title,
+            fc.author,
+            fc.location,
+            fc.source_url,
+            fc.note,
+            fc.highlighted_at,
+            serde_json::to_string(&fc.metadata)?,
+            fc.id,
+        ],
+    )?;
+    Ok(updated > 0)
+}
+
 pub fn delete_flashcard(fc_id: usize, conn: &Connection) -> Result<()> {
-    conn.execute("DELETE from flashcard where id = ?1", [&fc_id.to_string()])?;
+    delete_flashcards(&[fc_id], conn)?;
     Ok(())
 }
 
+///All the cards matching the selection, in the same order we display them in
+pub fn find_flashcards(selection: &CardSelection, conn: &Connection) -> Result<Vec<FlashCard>> {
+    let mut clauses = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+    if let Some(query) = &selection.query {
+        clauses.push(
+            "(title LIKE ? ESCAPE '\\' OR body LIKE ? ESCAPE '\\' OR author LIKE ? ESCAPE '\\')",
+        );
+        for _ in 0..3 {
+            values.push(Value::Text(like_pattern(query)));
+        }
+    }
+    if let Some(title) = &selection.title {
+        clauses.push("title LIKE ? ESCAPE '\\'");
+        values.push(Value::Text(like_pattern(title)));
+    }
+    if let Some((from, to)) = selection.ids {
+        clauses.push("id BETWEEN ? AND ?");
+        values.push(Value::Integer(from as i64));
+        values.push(Value::Integer(to as i64));
+    }
+    let where_clause = if clauses.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", clauses.join(" AND "))
+    };
+
+    let mut qry = conn.prepare(&format!(
+        "SELECT {} FROM flashcard {} ORDER BY id",
+        FLASHCARD_COLUMNS, where_clause
+    ))?;
+    let cards = qry
+        .query_map(params_from_iter(values), flashcard_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(cards)
+}
+
+///`%text%` for LIKE, with the text's own `%` and `_` escaped so `100%` means just that
+fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+///Delete all the cards in one go, returns how many went
+pub fn delete_flashcards(ids: &[usize], conn: &Connection) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut deleted = 0;
+    {
+        let mut stmt = tx.prepare("DELETE FROM flashcard WHERE id = ?1")?;
+        let mut reviews = tx.prepare("DELETE FROM review_log WHERE card_id = ?1")?;
+        for id in ids {
+            deleted += stmt.execute(params![id])?;
+            reviews.execute(params![id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(deleted)
+}
+
+///Replace the tags on all the cards
+pub fn retag_flashcards(ids: &[usize], tags: &str, conn: &Connection) -> Result<usize> {
+    update_column_for_flashcards(ids, "tags", &normalise_tags(tags), conn)
+}
+
+pub fn prioritise_flashcards(ids: &[usize], priority: usize, conn: &Connection) -> Result<usize> {
+    update_column_for_flashcards(ids, "priority", &priority.to_string(), conn)
+}
+
+pub fn move_flashcards_to_deck(ids: &[usize], deck: &str, conn: &Connection) -> Result<usize> {
+    update_column_for_flashcards(ids, "deck", deck.trim(), conn)
+}
+
+fn update_column_for_flashcards(
+    ids: &[usize],
+    column: &str,
+    value: &str,
+    conn: &Connection,
+) -> Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut updated = 0;
+    {
+        let mut stmt = tx.prepare(&format!(
+            "UPDATE flashcard SET {} = ?1, last_update = CURRENT_TIMESTAMP WHERE id = ?2",
+            column
+        ))?;
+        for id in ids {
+            updated += stmt.execute(params![value, id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+
+///Tags are stored comma separated, without whitespace or empties
+pub fn normalise_tags(tags: &str) -> String {
+    tags.split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 pub fn construct_title_report(conn: &Connection) -> Result<CardTitleReport> {
     let mut qry = conn.prepare("SELECT title, COUNT(*) FROM flashcard GROUP BY title")?;
     let mut report = CardTitleReport::new();
@@ -120,3 +668,391 @@ pub fn construct_title_report(conn: &Connection) -> Result<CardTitleReport> {
     }
     Ok(report)
 }
+
+pub fn construct_author_report(conn: &Connection) -> Result<Vec<CountLine>> {
+    count_lines(
+        "SELECT author, COUNT(*) FROM flashcard GROUP BY author ORDER BY COUNT(*) DESC, author",
+        conn,
+    )
+}
+
+pub fn construct_monthly_report(conn: &Connection) -> Result<Vec<CountLine>> {
+    count_lines(
+        "SELECT strftime('%Y-%m', create_date), COUNT(*) FROM flashcard GROUP BY 1 ORDER BY 1",
+        conn,
+    )
+}
+
+fn count_lines(sql: &str, conn: &Connection) -> Result<Vec<CountLine>> {
+    let mut qry = conn.prepare(sql)?;
+    let lines = qry
+        .query_map([], |row| {
+            Ok(CountLine {
+                name: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                count: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(lines)
+}
+
+///The limit cards seen the most, or the least if most is false
+pub fn fetch_view_extremes(most: bool, limit: usize, conn: &Connection) -> Result<Vec<CardViews>> {
+    let order = if most { "DESC" } else { "ASC" };
+    let mut qry = conn.prepare(&format!(
+        "SELECT id, title, view_count FROM flashcard ORDER BY view_count {}, id LIMIT ?1",
+        order
+    ))?;
+    let views = qry
+        .query_map(params![limit], |row| {
+            Ok(CardViews {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                view_count: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(views)
+}
+
+pub fn average_body_length(conn: &Connection) -> Result<f64> {
+    let avg: Option<f64> =
+        conn.query_row("SELECT AVG(LENGTH(TRIM(body))) FROM flashcard", [], |row| {
+            row.get(0)
+        })?;
+    Ok(avg.unwrap_or_default())
+}
+
+///Cards with nothing to show, or with nothing to say where they came from
+pub fn fetch_problem_cards(conn: &Connection) -> Result<Vec<ProblemCard>> {
+    let mut qry = conn.prepare(
+        "SELECT id, title,
+                CASE WHEN TRIM(COALESCE(body, '')) = '' THEN 'empty body' ELSE 'no title' END
+         FROM flashcard
+         WHERE TRIM(COALESCE(body, '')) = '' OR TRIM(COALESCE(title, '')) = ''
+         ORDER BY id",
+    )?;
+    let cards = qry
+        .query_map([], |row| {
+            Ok(ProblemCard {
+                id: row.get(0)?,
+                title: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                problem: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(cards)
+}
+
+///How many days of reviews the stats screen charts
+pub const STATS_DAYS: usize = 30;
+
+///What the stats screen shows, all worked out from the review log
+#[derive(Debug, Default)]
+pub struct StudyStats {
+    ///Oldest first, one entry per day including days without reviews
+    pub reviews_per_day: Vec<(String, u64)>,
+    ///Days in a row, up to today or yesterday, with at least one review
+    pub streak: usize,
+    pub total_reviews: usize,
+    pub correct: usize,
+    pub graded: usize,
+    ///Biggest titles first
+    pub cards_per_title: Vec<(String, u64)>,
+}
+
+impl StudyStats {
+    pub fn accuracy(&self) -> Option<f64> {
+        (self.graded > 0).then(|| self.correct as f64 / self.graded as f64)
+    }
+}
+
+pub fn construct_study_stats(conn: &Connection) -> Result<StudyStats> {
+    let mut per_day = conn.prepare(&format!(
+        "WITH RECURSIVE days(day) AS (
+             SELECT date('now', '-{} days')
+             UNION ALL SELECT date(day, '+1 day') FROM days WHERE day < date('now'))
+         SELECT day, (SELECT COUNT(*) FROM review_log WHERE date(reviewed_at) = day)
+         FROM days ORDER BY day",
+        STATS_DAYS - 1
+    ))?;
+    let reviews_per_day = per_day
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let today: i64 = conn.query_row(
+        "SELECT CAST(julianday(date('now')) AS INTEGER)",
+        [],
+        |row| row.get(0),
+    )?;
+    let mut review_days = conn.prepare(
+        "SELECT DISTINCT CAST(julianday(date(reviewed_at)) AS INTEGER) FROM review_log ORDER BY 1 DESC",
+    )?;
+    let days = review_days
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+
+    let (total_reviews, graded, correct) = conn.query_row(
+        "SELECT COUNT(*), COUNT(grade), COALESCE(SUM(grade), 0) FROM review_log",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+
+    let mut cards_per_title = construct_title_report(conn)?
+        .report_lines
+        .into_iter()
+        .map(|line| (line.title.trim().to_string(), line.title_count as u64))
+        .collect::<Vec<_>>();
+    cards_per_title.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    Ok(StudyStats {
+        reviews_per_day,
+        streak: streak_length(today, &days),
+        total_reviews,
+        correct,
+        graded,
+        cards_per_title,
+    })
+}
+
+///Consecutive days counting back from today, given julian days newest first.
+///Not having reviewed yet today doesn't break the streak
+fn streak_length(today: i64, days: &[i64]) -> usize {
+    let mut expected = match days.first() {
+        Some(&day) if day == today || day == today - 1 => day,
+        _ => return 0,
+    };
+    let mut streak = 0;
+    for &day in days {
+        if day != expected {
+            break;
+        }
+        streak += 1;
+        expected -= 1;
+    }
+    streak
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn card(title: &str, body: &str) -> FlashCard {
+        FlashCard {
+            title: title.to_string(),
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_data_version_sees_other_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let tui = Connection::open(dir.path().join("rashcard.db")).unwrap();
+        init_table(&tui).unwrap();
+        let importer = Connection::open(dir.path().join("rashcard.db")).unwrap();
+
+        let before = data_version(&tui).unwrap();
+        //our own writes aren't news to us
+        save_flashcard_object(&card("Title", "mine"), &tui).unwrap();
+        assert_eq!(before, data_version(&tui).unwrap());
+        save_flashcard_object(&card("Title", "imported"), &importer).unwrap();
+        assert_ne!(before, data_version(&tui).unwrap());
+        assert_eq!(2, fetch_initial_flash_card_count(false, &tui).unwrap());
+    }
+
+    #[test]
+    pub fn test_split_title_blobs() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        for (title, author) in [
+            ("Letters from a Stoic\nSeneca\n", ""),
+            ("Walden\n   \n", ""),
+            ("Meditations", ""),
+            ("Enchiridion\nArrian\n", "Epictetus"),
+        ] {
+            conn.execute(
+                "INSERT INTO flashcard(title, body, author) VALUES (?1, 'body', ?2)",
+                params![title, author],
+            )
+            .unwrap();
+        }
+        let rows = || {
+            let mut qry = conn
+                .prepare("SELECT title, source_title, author FROM flashcard ORDER BY id")
+                .unwrap();
+            qry.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<(String, String, String)>>>()
+                .unwrap()
+        };
+        let owned = |(title, source_title, author): (&str, &str, &str)| {
+            (
+                title.to_string(),
+                source_title.to_string(),
+                author.to_string(),
+            )
+        };
+
+        //the migration runs as the table is set up
+        init_table(&conn).unwrap();
+        let migrated = rows();
+        assert_eq!(
+            vec![
+                owned(("Letters from a Stoic", "Letters from a Stoic", "Seneca")),
+                owned(("Walden", "Walden", "")),
+                owned(("Meditations", "", "")),
+                owned(("Enchiridion\nArrian\n", "", "Epictetus")),
+            ],
+            migrated
+        );
+        init_table(&conn).unwrap();
+        assert_eq!(migrated, rows());
+    }
+
+    #[test]
+    pub fn test_favourites_only_filter() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        let ids = ["one", "two", "three", "four"]
+            .iter()
+            .map(|body| save_flashcard_object(&card("Title", body), &conn).unwrap())
+            .collect::<Vec<_>>();
+        assert!(toggle_favourite(ids[1], &conn).unwrap());
+        assert!(toggle_favourite(ids[3], &conn).unwrap());
+        assert_eq!(4, fetch_initial_flash_card_count(false, &conn).unwrap());
+        assert_eq!(2, fetch_initial_flash_card_count(true, &conn).unwrap());
+
+        //random picks an offset below the count, sequential steps through them
+        let starred = (0..2)
+            .map(|offset| next_flashcard(offset, true, &conn).unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert!(starred.iter().all(|fc| fc.favourite));
+        assert_eq!(
+            vec![ids[1], ids[3]],
+            starred.iter().map(|fc| fc.id).collect::<Vec<_>>()
+        );
+        assert!(next_flashcard(2, true, &conn).unwrap().is_none());
+        assert_eq!(2, fetch_card_weights(true, &conn).unwrap().len());
+
+        //a new starred card is counted, as the TUI recounts after saving
+        save_flashcard_object(
+            &FlashCard {
+                favourite: true,
+                ..card("Title", "five")
+            },
+            &conn,
+        )
+        .unwrap();
+        assert_eq!(3, fetch_initial_flash_card_count(true, &conn).unwrap());
+
+        assert!(!toggle_favourite(ids[1], &conn).unwrap());
+        assert_eq!(2, fetch_initial_flash_card_count(true, &conn).unwrap());
+        assert_eq!(5, fetch_initial_flash_card_count(false, &conn).unwrap());
+    }
+
+    #[test]
+    pub fn test_find_flashcards_takes_wildcards_literally() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        for fc in [
+            card("Effort", "Give 100% today"),
+            card("Effort", "Give 1000 today"),
+            card("snake_case", "names"),
+            card("snakeXcase", "names"),
+        ] {
+            save_flashcard_object(&fc, &conn).unwrap();
+        }
+        let find = |query: Option<&str>, title: Option<&str>| {
+            let selection = CardSelection {
+                query: query.map(str::to_string),
+                title: title.map(str::to_string),
+                ids: None,
+            };
+            find_flashcards(&selection, &conn).unwrap().len()
+        };
+        assert_eq!(1, find(Some("100%"), None));
+        assert_eq!(1, find(None, Some("snake_")));
+        assert_eq!(2, find(Some("Give"), None));
+    }
+
+    #[test]
+    pub fn test_flashcard_from_text() {
+        let fc = FlashCard::from_text("\n  Meditations\nWaste no more time\narguing\n");
+        assert_eq!("Meditations", fc.title);
+        assert_eq!("Waste no more time\narguing\n", fc.body);
+        assert!(crate::validate::check_flashcard(fc).is_valid());
+
+        //a body without a title line gets moved up into the title, leaving no body
+        let fc = FlashCard::from_text("\n\nWaste no more time arguing");
+        assert_eq!("Waste no more time arguing", fc.title);
+        assert_eq!("", fc.body);
+        assert!(!crate::validate::check_flashcard(fc).is_valid());
+
+        let checked = crate::validate::check_flashcard(FlashCard::from_text(" \n\t\n  \n"));
+        assert!(!checked.is_valid());
+        assert_eq!(
+            vec![
+                crate::validate::CardProblem::EmptyBody,
+                crate::validate::CardProblem::EmptyTitle
+            ],
+            checked.problems
+        );
+    }
+
+    #[test]
+    pub fn test_study_stats() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        let first = save_flashcard_object(&card("Meditations", "one"), &conn).unwrap();
+        let second = save_flashcard_object(&card("Meditations", "two"), &conn).unwrap();
+        let third = save_flashcard_object(&card("Walden", "three"), &conn).unwrap();
+        for (card_id, days_ago, grade) in [
+            (first, 0, Some(true)),
+            (second, 0, Some(false)),
+            (first, 1, Some(true)),
+            (third, 5, None),
+        ] {
+            conn.execute(
+                "INSERT INTO review_log(card_id, reviewed_at, grade)
+                 VALUES (?1, datetime('now', ?2), ?3)",
+                params![card_id, format!("-{} days", days_ago), grade],
+            )
+            .unwrap();
+        }
+        //viewing alone isn't studying
+        record_view(third, &conn).unwrap();
+
+        let stats = construct_study_stats(&conn).unwrap();
+        assert_eq!(STATS_DAYS, stats.reviews_per_day.len());
+        let counts = stats
+            .reviews_per_day
+            .iter()
+            .map(|(_, count)| *count)
+            .collect::<Vec<_>>();
+        assert_eq!([1, 0, 0, 0, 1, 2], counts[STATS_DAYS - 6..]);
+        assert_eq!(2, stats.streak);
+        assert_eq!(4, stats.total_reviews);
+        assert_eq!(3, stats.graded);
+        assert_eq!(2, stats.correct);
+        assert_eq!(
+            vec![("Meditations".to_string(), 2), ("Walden".to_string(), 1)],
+            stats.cards_per_title
+        );
+
+        //a deleted card takes its reviews with it
+        delete_flashcard(first, &conn).unwrap();
+        let stats = construct_study_stats(&conn).unwrap();
+        assert_eq!(2, stats.total_reviews);
+        assert_eq!(1, stats.streak);
+    }
+
+    #[test]
+    pub fn test_streak_length() {
+        assert_eq!(0, streak_length(100, &[]));
+        assert_eq!(3, streak_length(100, &[100, 99, 98, 96]));
+        assert_eq!(2, streak_length(100, &[99, 98]));
+        assert_eq!(0, streak_length(100, &[98, 97]));
+    }
+}
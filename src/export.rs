@@ -0,0 +1,162 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::db::FlashCard;
+use crate::import::{ORG_FIELD_PROPERTIES, READWISE_HEADER};
+
+///Pick the format from the file name, csv files get the Readwise layout
+///and directories an org file per title
+pub fn export_flashcards(cards: &[FlashCard], fp: &str) -> Result<()> {
+    if fp.ends_with('/') || Path::new(fp).is_dir() {
+        export_org(cards, fp)
+    } else if fp.to_lowercase().ends_with(".csv") {
+        export_readwise_csv(cards, fp)
+    } else {
+        export_markdown(cards, fp)
+    }
+}
+
+///Write the cards out as markdown, one section per card,
+///with the body quoted the way Yomu does it
+pub fn export_markdown(cards: &[FlashCard], fp: &str) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(fp)?);
+    for fc in cards {
+        writeln!(writer, "# {}", fc.title.trim().replace('\n', " - "))?;
+        if !fc.tags.is_empty() {
+            writeln!(writer, "tags: {}", fc.tags)?;
+        }
+        if !fc.deck.is_empty() {
+            writeln!(writer, "deck: {}", fc.deck)?;
+        }
+        writeln!(writer)?;
+        for line in fc.body.lines() {
+            writeln!(writer, ">{}", line)?;
+        }
+        writeln!(writer, "\n---\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+///Write the cards out in the shape Readwise exports, so they can go back the other way
+pub fn export_readwise_csv(cards: &[FlashCard], fp: &str) -> Result<()> {
+    let mut writer = csv::Writer::from_path(fp)?;
+    writer.write_record(READWISE_HEADER)?;
+    for fc in cards {
+        let metadata = |key: &str| fc.metadata.get(key).map(String::as_str).unwrap_or_default();
+        let book_title = if fc.source_title.is_empty() {
+            &fc.title
+        } else {
+            &fc.source_title
+        };
+        writer.write_record([
+            fc.body.as_str(),
+            book_title,
+            &fc.author,
+            metadata("amazon_book_id"),
+            &fc.note,
+            metadata("color"),
+            &fc.tags,
+            metadata("location_type"),
+            &fc.location,
+            &fc.highlighted_at,
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+///Write one org file per title into the directory, each card a headline with
+///its tags, and everything else about it in a `PROPERTIES` drawer
+pub fn export_org(cards: &[FlashCard], dir: &str) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut per_title: BTreeMap<&str, Vec<&FlashCard>> = BTreeMap::new();
+    for fc in cards {
+        per_title.entry(fc.title.trim()).or_default().push(fc);
+    }
+    let mut used_names = HashSet::new();
+    for (title, cards) in per_title {
+        let mut name = org_file_name(title);
+        let stem = name.clone();
+        let mut n = 1;
+        while !used_names.insert(name.clone()) {
+            n += 1;
+            name = format!("{}-{}", stem, n);
+        }
+        let path = Path::new(dir).join(format!("{}.org", name));
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "#+TITLE: {}", title.replace('\n', " - "))?;
+        for fc in cards {
+            write_org_card(&mut writer, fc)?;
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn write_org_card(writer: &mut impl Write, fc: &FlashCard) -> Result<()> {
+    let mut headline = format!("* {}", fc.title.trim().replace('\n', " - "));
+    let tags = fc
+        .tags
+        .split(',')
+        .map(|tag| tag.trim().replace(' ', "_"))
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<_>>();
+    if !tags.is_empty() {
+        headline.push_str(&format!(" :{}:", tags.join(":")));
+    }
+    writeln!(writer, "{}", headline)?;
+    let fields = ORG_FIELD_PROPERTIES.iter().zip([
+        &fc.author,
+        &fc.source_title,
+        &fc.location,
+        &fc.source_url,
+        &fc.note,
+        &fc.highlighted_at,
+        &fc.deck,
+    ]);
+    let properties = fields
+        .map(|(key, value)| (key.to_string(), value))
+        .chain(fc.metadata.iter().map(|(key, value)| (key.clone(), value)))
+        .filter(|(_, value)| !value.trim().is_empty())
+        .collect::<Vec<_>>();
+    if !properties.is_empty() {
+        writeln!(writer, ":PROPERTIES:")?;
+        for (key, value) in properties {
+            writeln!(writer, ":{}: {}", key, value.replace('\n', " "))?;
+        }
+        writeln!(writer, ":END:")?;
+    }
+    for line in fc.body.lines() {
+        //a line starting with a star would be read back as a headline
+        if line.starts_with('*') {
+            write!(writer, " ")?;
+        }
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
+
+///Titles made safe to use as file names
+fn org_file_name(title: &str) -> String {
+    let name = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let name = name.trim();
+    if name.is_empty() {
+        "untitled".to_string()
+    } else {
+        name.to_string()
+    }
+}
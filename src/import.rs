@@ -1,29 +1,941 @@
-use std::io::{BufRead, BufReader, Read};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
-use crate::db::{save_flashcard_object, FlashCard};
-use anyhow::Result;
-use rusqlite::Connection;
+use crate::bulk::{confirm, parse_id_range, prompt_line};
+use crate::db::{self, flashcard_exists, FlashCard, FlashCardInserter};
+use crate::lua::{array_values, parse_lua_table};
+use crate::plugin;
+use crate::validate::{check_flashcard, CheckedCard};
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use notify::{EventKind, RecursiveMode, Watcher};
+use regex::Regex;
+use rusqlite::{Connection, OpenFlags, Transaction};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
 
-///Import a file using the Yomu export format
-///Top line will be used as the title for flashcards, prefixed with a monotonically increasing
-///number       
-pub fn import_yomu_quotes(fp: &str, conn: &Connection) -> Result<()> {
-    let file = std::fs::File::open(fp)?;
-    let mut reader = BufReader::new(file);
+///File extensions we look at when importing a whole directory
+const SUPPORTED_EXTENSIONS: [&str; 10] = [
+    "md",
+    "txt",
+    "csv",
+    "json",
+    "lua",
+    "calibre_highlights",
+    "mrexpt",
+    "html",
+    "htm",
+    "org",
+];
 
-    let mut first_line = String::new();
-    reader.read_line(&mut first_line)?;
-    let (title, author) = extract_yomu_title_author(&first_line);
-    let title_with_author = format!("{}\n{}\n", title, author);
-    let mut file_contents = String::new();
-    reader.read_to_string(&mut file_contents)?;
+///Readers write their exports in bursts, wait this long for things to go quiet
+const WATCH_SETTLE_MILLIS: u64 = 500;
 
-    for fc in extract_yomu_flashcards(&title_with_author, file_contents)? {
-        save_flashcard_object(&fc, conn)?;
+///Text highlight formats simple enough to share one Importer impl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Readwise,
+    KOReaderJson,
+    KOReaderMarkdown,
+    KOReaderLua,
+    Calibre,
+    MoonReader,
+    MoonReaderShared,
+    PlayBooks,
+    Markdown,
+    Org,
+}
+
+///How an import should go, from the command line
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    ///Import files again even if they haven't changed
+    pub force: bool,
+    ///Heading level that starts a card in plain markdown notes
+    pub heading_level: usize,
+    ///Epub paragraphs that match this become cards
+    pub passage_pattern: Option<Regex>,
+    ///Epub paragraphs inside this markup become cards
+    pub passage_markup: Option<MarkupSelector>,
+    ///Hand every file to the importer with this name instead of sniffing
+    pub format: Option<String>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            force: false,
+            heading_level: DEFAULT_HEADING_LEVEL,
+            passage_pattern: None,
+            passage_markup: None,
+            format: None,
+        }
+    }
+}
+
+///A file as the importers see it, read once and handed to each in turn
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub bytes: Vec<u8>,
+}
+
+impl SourceFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            bytes: std::fs::read(path)?,
+        })
+    }
+
+    ///The contents as text, which is what every format but epub is
+    pub fn text(&self) -> Result<&str> {
+        std::str::from_utf8(&self.bytes)
+            .with_context(|| format!("{} is not a text file", self.path.display()))
+    }
+
+    pub fn has_extension(&self, ext: &str) -> bool {
+        self.path
+            .extension()
+            .is_some_and(|found| found.eq_ignore_ascii_case(ext))
+    }
+}
+
+///What an importer makes of a file: the cards, metadata and all, and anything
+///about the file as a whole the user should hear about
+#[derive(Debug, Default)]
+pub struct ParsedImport {
+    pub cards: Vec<FlashCard>,
+    pub warnings: Vec<String>,
+}
+
+impl From<Vec<FlashCard>> for ParsedImport {
+    fn from(cards: Vec<FlashCard>) -> Self {
+        Self {
+            cards,
+            ..Default::default()
+        }
+    }
+}
+
+///A highlight format we can read
+pub trait Importer {
+    ///Short name, for `import --list-formats`
+    fn name(&self) -> &str;
+    ///One line on what writes this format
+    fn description(&self) -> &str;
+    ///Does the file look like one of ours. Importers are asked in registry order
+    ///and the first to say yes gets the file, so sniff as tightly as the format allows
+    fn sniff(&self, file: &SourceFile) -> bool;
+    fn parse(&self, file: &SourceFile, options: &ImportOptions) -> Result<ParsedImport>;
+    ///The options that change what parse makes of a file, empty if none do.
+    ///They go into the hash recorded for the file, see source_hash
+    fn options_key(&self, _options: &ImportOptions) -> String {
+        String::new()
+    }
+}
+
+///Every importer we have, in the order they get to sniff a file.
+///Loose formats like plain markdown go last so they don't steal files from stricter ones,
+///then come the plugins, see plugin::ExternalImporter
+pub fn importers() -> Vec<Box<dyn Importer>> {
+    let mut importers: Vec<Box<dyn Importer>> = vec![
+        Box::new(EpubImporter),
+        Box::new(ImportFormat::MoonReader),
+        Box::new(ImportFormat::PlayBooks),
+        Box::new(ImportFormat::MoonReaderShared),
+        Box::new(ImportFormat::Calibre),
+        Box::new(ImportFormat::KOReaderJson),
+        Box::new(ImportFormat::KOReaderLua),
+        Box::new(ImportFormat::KOReaderMarkdown),
+        Box::new(ImportFormat::Readwise),
+        Box::new(YomuImporter),
+        Box::new(ImportFormat::Org),
+        Box::new(ReadEraImporter),
+        Box::new(ImportFormat::Markdown),
+    ];
+    for external in plugin::plugins() {
+        importers.push(Box::new(external.clone()));
+    }
+    importers
+}
+
+///The first importer that recognises the file
+pub fn detect_importer(file: &SourceFile) -> Option<Box<dyn Importer>> {
+    importers()
+        .into_iter()
+        .find(|importer| importer.sniff(file))
+}
+
+///The importer --format asked for
+fn named_importer(name: &str) -> Result<Box<dyn Importer>> {
+    match importers()
+        .into_iter()
+        .find(|importer| importer.name() == name)
+    {
+        Some(importer) => Ok(importer),
+        None => bail!("no format called {}, see --list-formats", name),
+    }
+}
+
+///For `import --list-formats`
+pub fn print_formats() {
+    let importers = importers();
+    let width = importers
+        .iter()
+        .map(|importer| importer.name().len())
+        .max()
+        .unwrap_or_default();
+    for importer in &importers {
+        println!(
+            "{:width$}  {}",
+            importer.name().cyan(),
+            importer.description()
+        );
+    }
+    println!(
+        "{:width$}  Apple Books databases, imported with --apple-books",
+        "apple-books".cyan()
+    );
+}
+
+impl ImportFormat {
+    ///Does the text look like this format, see importers for the order they are tried in
+    fn matches(&self, contents: &str) -> bool {
+        let first_line = contents
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or_default();
+        let trimmed = contents.trim_start();
+        match self {
+            ImportFormat::MoonReader => {
+                first_line.trim().parse::<u32>().is_ok()
+                    && contents.lines().any(|line| line.trim() == "#")
+                    && contents.lines().any(|line| line.starts_with("indent:"))
+            }
+            ImportFormat::PlayBooks => {
+                let html = trimmed.get(..14).unwrap_or(trimmed).to_lowercase();
+                (html.starts_with("<html") || html.starts_with("<!doctype html"))
+                    && contents.contains("Play Books")
+            }
+            ImportFormat::MoonReaderShared => {
+                //title, author, then straight into chapters and highlights
+                let is_entry = |line: &str| line.starts_with(['◆', '▪']);
+                let mut lines = contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty());
+                let header = [lines.next(), lines.next()];
+                header
+                    .iter()
+                    .all(|line| line.is_some_and(|line| !is_entry(line)))
+                    && lines.next().is_some_and(is_entry)
+                    && contents
+                        .lines()
+                        .any(|line| line.trim_start().starts_with('▪'))
+            }
+            ImportFormat::Calibre => {
+                trimmed.starts_with('{') && contents.contains("\"highlighted_text\"")
+            }
+            ImportFormat::KOReaderJson => {
+                trimmed.starts_with(['{', '[']) && contents.contains("\"entries\"")
+            }
+            ImportFormat::KOReaderLua => {
+                (trimmed.starts_with("--") || trimmed.starts_with("return"))
+                    && (contents.contains("[\"annotations\"]")
+                        || contents.contains("[\"highlight\"]"))
+            }
+            ImportFormat::KOReaderMarkdown => {
+                first_line.starts_with("# ")
+                    && contents.lines().any(|line| line.starts_with("### Page "))
+            }
+            ImportFormat::Readwise => {
+                first_line
+                    .trim_start_matches('\u{feff}')
+                    .starts_with(READWISE_HEADER[0])
+                    && first_line.contains(READWISE_HEADER[1])
+            }
+            ImportFormat::Org => contents.lines().any(|line| {
+                line.get(..8)
+                    .is_some_and(|start| start.eq_ignore_ascii_case("#+TITLE:"))
+                    || line.trim().eq_ignore_ascii_case(":PROPERTIES:")
+            }),
+            ImportFormat::Markdown => {
+                first_line.trim() == "---"
+                    || contents
+                        .lines()
+                        .any(|line| markdown_heading(line).is_some())
+            }
+        }
+    }
+
+    pub fn parse_text(&self, contents: String, options: &ImportOptions) -> Result<Vec<FlashCard>> {
+        match self {
+            ImportFormat::Readwise => parse_readwise_csv(contents),
+            ImportFormat::KOReaderJson => parse_koreader_json(&contents),
+            ImportFormat::KOReaderMarkdown => Ok(parse_koreader_markdown(&contents)),
+            ImportFormat::KOReaderLua => parse_koreader_lua(&contents),
+            ImportFormat::Calibre => parse_calibre_annotations(&contents),
+            ImportFormat::MoonReader => Ok(parse_moon_reader_mrexpt(&contents)),
+            ImportFormat::MoonReaderShared => Ok(parse_moon_reader_shared(&contents)),
+            ImportFormat::PlayBooks => Ok(parse_play_books_html(&contents)),
+            ImportFormat::Markdown => Ok(parse_markdown_notes(&contents, options.heading_level)),
+            ImportFormat::Org => Ok(parse_org(&contents)),
+        }
+    }
+}
+
+impl Importer for ImportFormat {
+    fn name(&self) -> &str {
+        match self {
+            ImportFormat::Readwise => "readwise",
+            ImportFormat::KOReaderJson => "koreader-json",
+            ImportFormat::KOReaderMarkdown => "koreader-markdown",
+            ImportFormat::KOReaderLua => "koreader-lua",
+            ImportFormat::Calibre => "calibre",
+            ImportFormat::MoonReader => "moon-reader",
+            ImportFormat::MoonReaderShared => "moon-reader-shared",
+            ImportFormat::PlayBooks => "play-books",
+            ImportFormat::Markdown => "markdown",
+            ImportFormat::Org => "org",
+        }
+    }
+
+    fn description(&self) -> &str {
+        match self {
+            ImportFormat::Readwise => "Readwise csv export",
+            ImportFormat::KOReaderJson => "KOReader highlights exported as JSON",
+            ImportFormat::KOReaderMarkdown => "KOReader highlights exported as markdown",
+            ImportFormat::KOReaderLua => "KOReader metadata.*.lua sidecar files",
+            ImportFormat::Calibre => "Calibre viewer annotation export",
+            ImportFormat::MoonReader => "Moon+ Reader .mrexpt backup",
+            ImportFormat::MoonReaderShared => "Moon+ Reader notes shared as text",
+            ImportFormat::PlayBooks => "Google Play Books notes from Drive, saved as html",
+            ImportFormat::Markdown => "Plain markdown or Obsidian notes, a card per heading",
+            ImportFormat::Org => "Org mode files, a card per headline",
+        }
+    }
+
+    ///Org files are known by their extension too, plenty of them have neither
+    ///a title nor properties
+    fn sniff(&self, file: &SourceFile) -> bool {
+        (matches!(self, ImportFormat::Org) && file.has_extension("org"))
+            || file.text().is_ok_and(|contents| self.matches(contents))
+    }
+
+    fn parse(&self, file: &SourceFile, options: &ImportOptions) -> Result<ParsedImport> {
+        Ok(self.parse_text(file.text()?.to_string(), options)?.into())
+    }
+}
+
+///What became of one file in a directory import
+pub enum FileOutcome {
+    Imported(ImportSummary),
+    AlreadyImported,
+    Unsupported,
+}
+
+///What is in a file, None if no importer recognises it
+fn read_source_file(file: &Path, options: &ImportOptions) -> Result<Option<ParsedImport>> {
+    let source = SourceFile::read(file)?;
+    match source_importer(&source, options)? {
+        Some(importer) => Ok(Some(parse_source(&source, importer.as_ref(), options)?)),
+        None => Ok(None),
+    }
+}
+
+///The importer --format asked for, or the first to recognise the file
+fn source_importer(
+    source: &SourceFile,
+    options: &ImportOptions,
+) -> Result<Option<Box<dyn Importer>>> {
+    match &options.format {
+        Some(name) => Ok(Some(named_importer(name)?)),
+        None => Ok(detect_importer(source)),
+    }
+}
+
+fn parse_source(
+    source: &SourceFile,
+    importer: &dyn Importer,
+    options: &ImportOptions,
+) -> Result<ParsedImport> {
+    let mut parsed = importer.parse(source, options)?;
+    title_untitled_cards(&mut parsed.cards, &source.path);
+    Ok(parsed)
+}
+
+///Some exports don't say which book they are from, the file name usually does
+fn title_untitled_cards(cards: &mut [FlashCard], file: &Path) {
+    let Some(stem) = file.file_stem().and_then(|stem| stem.to_str()) else {
+        return;
+    };
+    for fc in cards.iter_mut().filter(|fc| fc.title.trim().is_empty()) {
+        fc.title = stem.to_string();
+        if fc.source_title.trim().is_empty() {
+            fc.source_title = stem.to_string();
+        }
+    }
+}
+
+///Import every supported file under the paths, which can be files, directories or globs.
+///Files already imported with the same contents are skipped unless forced.
+///One file failing doesn't stop the rest, but the import as a whole is an error
+pub fn import_paths(paths: &[String], options: &ImportOptions, conn: &Connection) -> Result<()> {
+    let files = discover_files(paths)?;
+    if files.is_empty() {
+        println!("{}", "No files found to import".yellow());
+        return Ok(());
+    }
+    let mut imported_files = 0;
+    let mut imported_cards = 0;
+    let mut failed = 0;
+    for file in &files {
+        let outcome = import_source_file(file, options, conn);
+        if outcome.is_err() {
+            failed += 1;
+        }
+        if let Some(summary) = print_file_outcome(file, outcome) {
+            imported_files += 1;
+            imported_cards += summary.imported;
+        }
+    }
+    println!(
+        "Imported {} flashcards from {} of {} files",
+        imported_cards.to_string().purple(),
+        imported_files,
+        files.len()
+    );
+    if failed > 0 {
+        bail!("{} of {} files failed to import", failed, files.len());
+    }
+    Ok(())
+}
+
+///Preview every supported file under the paths without saving anything,
+///or with interactive, pick the cards to import from each file in turn
+pub fn preview_paths(
+    paths: &[String],
+    options: &ImportOptions,
+    interactive: bool,
+    conn: &Connection,
+) -> Result<()> {
+    let files = discover_files(paths)?;
+    if files.is_empty() {
+        println!("{}", "No files found to preview".yellow());
+        return Ok(());
+    }
+    let mut failed = 0;
+    for file in &files {
+        let fp = file.display().to_string();
+        let parsed = match read_source_file(file, options) {
+            Ok(Some(parsed)) => parsed,
+            Ok(None) => {
+                println!(
+                    "{}: {}",
+                    fp.cyan(),
+                    "not a format we know, skipping".yellow()
+                );
+                continue;
+            }
+            Err(e) => {
+                println!("{}: {} {}", fp.cyan(), "failed,".red(), e);
+                failed += 1;
+                continue;
+            }
+        };
+        println!("{}", fp.cyan());
+        for warning in &parsed.warnings {
+            println!("  {} {}", "warning,".yellow(), warning);
+        }
+        let previews = preview_flashcards(parsed.cards, conn)?;
+        if !interactive {
+            print_preview(&previews);
+        } else if let Some(cards) = choose_flashcards(previews)? {
+            save_numbered_flashcards(cards, conn)?.print_out(&fp);
+        } else {
+            println!("Nothing imported from {}", fp.cyan());
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} files could not be read", failed, files.len());
     }
     Ok(())
 }
 
+///One line or so per file, handing back the summary if anything was imported
+fn print_file_outcome(file: &Path, outcome: Result<FileOutcome>) -> Option<ImportSummary> {
+    let fp = file.display().to_string();
+    match outcome {
+        Ok(FileOutcome::Imported(summary)) => {
+            summary.print_out(&fp);
+            return Some(summary);
+        }
+        Ok(FileOutcome::AlreadyImported) => {
+            println!("{}: {}", fp.cyan(), "already imported, skipping".yellow())
+        }
+        Ok(FileOutcome::Unsupported) => {
+            println!(
+                "{}: {}",
+                fp.cyan(),
+                "not a format we know, skipping".yellow()
+            )
+        }
+        Err(e) => println!("{}: {} {}", fp.cyan(), "failed,".red(), e),
+    }
+    None
+}
+
+///Import what is already in the directory, then keep importing files as they are
+///created or changed until killed. A running TUI notices the new cards by itself
+pub fn watch_directory(dir: &str, options: &ImportOptions, conn: &Connection) -> Result<()> {
+    if !Path::new(dir).is_dir() {
+        bail!("{} is not a directory", dir);
+    }
+    //a bad file is no reason not to watch for good ones
+    if let Err(e) = import_paths(&[dir.to_string()], options, conn) {
+        println!("{}", e.to_string().red());
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(Path::new(dir), RecursiveMode::Recursive)?;
+    println!(
+        "Watching {} for highlight files, Ctrl+C to stop",
+        dir.cyan()
+    );
+
+    let settle = Duration::from_millis(WATCH_SETTLE_MILLIS);
+    loop {
+        let mut changed = BTreeSet::new();
+        let mut event = rx.recv()?;
+        loop {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    changed.extend(event.paths)
+                }
+                Ok(_) => {}
+                Err(e) => println!("{} {}", "watch error,".red(), e),
+            }
+            match rx.recv_timeout(settle) {
+                Ok(next) => event = next,
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        for file in changed.iter().filter(|path| is_supported_file(path)) {
+            print_file_outcome(file, import_source_file(file, options, conn));
+        }
+    }
+}
+
+///Expand directories and globs into the files under them, sorted and without repeats.
+///Files named outright are kept whatever their extension
+pub fn discover_files(paths: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.contains(['*', '?', '[']) {
+            for entry in glob::glob(path)? {
+                let entry = entry?;
+                if entry.is_dir() {
+                    files.extend(walk_supported_files(&entry));
+                } else {
+                    files.push(entry);
+                }
+            }
+        } else if Path::new(path).is_dir() {
+            files.extend(walk_supported_files(Path::new(path)));
+        } else {
+            files.push(PathBuf::from(path));
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn walk_supported_files(dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| is_supported_file(path))
+        .collect()
+}
+
+///Extensions we know, plus the names of any plugins
+fn is_supported_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                let ext = ext.to_lowercase();
+                SUPPORTED_EXTENSIONS.contains(&ext.as_str())
+                    || plugin::plugins().iter().any(|plugin| plugin.name == ext)
+            })
+}
+
+fn import_source_file(
+    file: &Path,
+    options: &ImportOptions,
+    conn: &Connection,
+) -> Result<FileOutcome> {
+    let source = SourceFile::read(file)?;
+    let Some(importer) = source_importer(&source, options)? else {
+        return Ok(FileOutcome::Unsupported);
+    };
+    //checked before parsing, an unchanged file needn't be readable with today's options
+    let hash = source_hash(&source.bytes, &importer.options_key(options));
+    if !options.force && unchanged_since_import(file, &hash, conn)? {
+        return Ok(FileOutcome::AlreadyImported);
+    }
+    let parsed = parse_source(&source, importer.as_ref(), options)?;
+    save_source_cards(file, &hash, parsed, options.force, conn)
+}
+
+///Was this file imported before with the contents it has now
+fn unchanged_since_import(file: &Path, hash: &str, conn: &Connection) -> Result<bool> {
+    let path = file.canonicalize()?.display().to_string();
+    db::import_source_exists(&path, hash, conn)
+}
+
+///Save the cards parsed out of a new or changed file, then remember the file and its hash
+fn save_source_cards(
+    file: &Path,
+    hash: &str,
+    parsed: ParsedImport,
+    force: bool,
+    conn: &Connection,
+) -> Result<FileOutcome> {
+    let mut cards = parsed.cards;
+    let path = file.canonicalize()?.display().to_string();
+    //a changed file mostly holds what we took from it last time, only the new cards are wanted
+    let mut already_imported = 0;
+    if !force && db::import_source_path_exists(&path, conn)? {
+        let mut new_cards = Vec::new();
+        for fc in cards {
+            let checked = check_flashcard(fc.clone());
+            if flashcard_exists(&checked.card.title, &checked.card.body, conn)? {
+                already_imported += 1;
+            } else {
+                new_cards.push(fc);
+            }
+        }
+        cards = new_cards;
+    }
+    //the cards and the record of where they came from go in together or not at all
+    let tx = conn.unchecked_transaction()?;
+    let mut summary = insert_checked_flashcards(number_cards(cards), &tx)?;
+    summary.already_imported = already_imported;
+    summary.file_warnings = parsed.warnings;
+    db::record_import_source(&path, hash, summary.imported, &tx)?;
+    tx.commit()?;
+    Ok(FileOutcome::Imported(summary))
+}
+
+///Hex sha256 of the file contents, so changed files get imported again
+fn content_hash(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+///The hash recorded for a file, with the importer options that shaped it after a `+`,
+///so the same epub run again with another --match is imported again
+fn source_hash(contents: &[u8], options_key: &str) -> String {
+    match options_key {
+        "" => content_hash(contents),
+        key => format!(
+            "{}+{}",
+            content_hash(contents),
+            content_hash(key.as_bytes())
+        ),
+    }
+}
+
+///What happened to each card in an import
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    ///Card number in the file and why it was left out
+    pub skipped: Vec<(usize, String)>,
+    ///Card number in the file and what looks off about it
+    pub warnings: Vec<(usize, String)>,
+    ///Cards left out because an earlier import of the same file brought them in
+    pub already_imported: usize,
+    ///What the importer had to say about the file as a whole
+    pub file_warnings: Vec<String>,
+    ///How long checking and saving took
+    pub elapsed: Duration,
+}
+
+impl ImportSummary {
+    pub fn cards_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.imported as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    pub fn print_out(&self, fp: &str) {
+        println!(
+            "Imported {} flashcards from {} in {:.2}s ({:.0} cards/s)",
+            self.imported.to_string().purple(),
+            fp.cyan(),
+            self.elapsed.as_secs_f64(),
+            self.cards_per_sec()
+        );
+        if self.already_imported > 0 {
+            println!(
+                "  {} cards were already imported from an earlier version",
+                self.already_imported
+            );
+        }
+        for warning in &self.file_warnings {
+            println!("  {} {}", "warning,".yellow(), warning);
+        }
+        for (number, reason) in &self.skipped {
+            println!("  card {}: {} {}", number, "skipped,".red(), reason);
+        }
+        for (number, warning) in &self.warnings {
+            println!("  card {}: {} {}", number, "warning,".yellow(), warning);
+        }
+    }
+}
+
+///Pair each card with its place in the file, starting at 1
+pub fn number_cards(cards: Vec<FlashCard>) -> Vec<(usize, FlashCard)> {
+    cards
+        .into_iter()
+        .enumerate()
+        .map(|(i, fc)| (i + 1, fc))
+        .collect()
+}
+
+///Clean up and check every card, saving the good ones and noting the rest,
+///all or nothing in one transaction: any error and the transaction is dropped,
+///rolling back every card saved before it.
+///The summary uses the numbers the cards came with, so a card picked out of
+///a preview is reported by its place in the file
+pub fn save_numbered_flashcards(
+    cards: Vec<(usize, FlashCard)>,
+    conn: &Connection,
+) -> Result<ImportSummary> {
+    let tx = conn.unchecked_transaction()?;
+    let summary = insert_checked_flashcards(cards, &tx)?;
+    tx.commit()?;
+    Ok(summary)
+}
+
+///The checking and saving behind save_numbered_flashcards, inside a transaction
+///the caller commits
+fn insert_checked_flashcards(
+    cards: Vec<(usize, FlashCard)>,
+    tx: &Transaction,
+) -> Result<ImportSummary> {
+    let start = Instant::now();
+    let mut inserter = FlashCardInserter::new(tx)?;
+    let mut summary = ImportSummary::default();
+    for (number, fc) in cards {
+        let checked = check_flashcard(fc);
+        if !checked.is_valid() {
+            summary.skipped.push((number, checked.describe_problems()));
+            continue;
+        }
+        if !checked.problems.is_empty() {
+            summary.warnings.push((number, checked.describe_problems()));
+        }
+        inserter.insert(&checked.card)?;
+        summary.imported += 1;
+    }
+    drop(inserter);
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+///A card as it would be imported, for --dry-run and --interactive
+#[derive(Debug)]
+pub struct PreviewCard {
+    ///Position in the file, starting at 1
+    pub number: usize,
+    pub checked: CheckedCard,
+    ///Already in the database, or earlier in the same file
+    pub duplicate: bool,
+}
+
+impl PreviewCard {
+    ///Would this card be worth importing
+    pub fn is_wanted(&self) -> bool {
+        self.checked.is_valid() && !self.duplicate
+    }
+}
+
+///Check the cards without saving anything
+pub fn preview_flashcards(cards: Vec<FlashCard>, conn: &Connection) -> Result<Vec<PreviewCard>> {
+    let mut seen = HashSet::new();
+    let mut previews = Vec::new();
+    for (i, fc) in cards.into_iter().enumerate() {
+        let checked = check_flashcard(fc);
+        let key = (checked.card.title.clone(), checked.card.body.clone());
+        let duplicate =
+            !seen.insert(key) || flashcard_exists(&checked.card.title, &checked.card.body, conn)?;
+        previews.push(PreviewCard {
+            number: i + 1,
+            checked,
+            duplicate,
+        });
+    }
+    Ok(previews)
+}
+
+pub fn print_preview(previews: &[PreviewCard]) {
+    for preview in previews {
+        let mut flags = Vec::new();
+        if preview.duplicate {
+            flags.push("duplicate".red().to_string());
+        }
+        if !preview.checked.problems.is_empty() {
+            let problems = preview.checked.describe_problems();
+            if preview.checked.is_valid() {
+                flags.push(problems.yellow().to_string());
+            } else {
+                flags.push(problems.red().to_string());
+            }
+        }
+        let first_line = preview.checked.card.body.lines().next().unwrap_or_default();
+        println!(
+            "{:>4} {} | {} {}",
+            preview.number,
+            preview.checked.card.title.cyan(),
+            first_line,
+            flags.join(" ")
+        );
+    }
+    let wanted = previews.iter().filter(|p| p.is_wanted()).count();
+    println!(
+        "{} of {} cards would be imported",
+        wanted.to_string().purple(),
+        previews.len()
+    );
+}
+
+///Show the preview and let the user leave cards out, duplicates and
+///broken cards are left out to start with. None if they back out.
+///Each card keeps its number from the preview
+pub fn choose_flashcards(previews: Vec<PreviewCard>) -> Result<Option<Vec<(usize, FlashCard)>>> {
+    print_preview(&previews);
+    let mut chosen = previews
+        .iter()
+        .filter(|p| p.is_wanted())
+        .map(|p| p.number)
+        .collect::<BTreeSet<_>>();
+    let numbers = previews.iter().map(|p| p.number).collect::<BTreeSet<_>>();
+    loop {
+        let answer = prompt_line("Leave out (eg 3,5-7), put back (eg +4), or Enter when done:")?;
+        if answer.is_empty() {
+            break;
+        }
+        for e in apply_choices(&answer, &numbers, &mut chosen) {
+            println!("{}", e.red());
+        }
+        println!(
+            "Importing cards: {}",
+            chosen
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+    }
+    if !confirm(&format!("Import {} cards?", chosen.len()))? {
+        return Ok(None);
+    }
+    Ok(Some(
+        previews
+            .into_iter()
+            .filter(|p| chosen.contains(&p.number))
+            .map(|p| (p.number, p.checked.card))
+            .collect(),
+    ))
+}
+
+///Leave out or put back the cards in an answer like `3,5-7,+4`, giving back
+///the parts that made no sense. Only numbers in the preview are put back,
+///however big the range
+fn apply_choices(
+    answer: &str,
+    numbers: &BTreeSet<usize>,
+    chosen: &mut BTreeSet<usize>,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for part in answer.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (put_back, range) = match part.strip_prefix('+') {
+            Some(range) => (true, range),
+            None => (false, part),
+        };
+        match parse_id_range(range) {
+            Ok((from, to)) if put_back => chosen.extend(numbers.range(from..=to)),
+            Ok((from, to)) => chosen.retain(|n| !(from..=to).contains(n)),
+            Err(e) => errors.push(e),
+        }
+    }
+    errors
+}
+
+///Yomu's markdown export. The first line has the title and author,
+///the quotes follow, `>` quoted and split by `---`
+pub struct YomuImporter;
+
+impl Importer for YomuImporter {
+    fn name(&self) -> &str {
+        "yomu"
+    }
+
+    fn description(&self) -> &str {
+        "Yomu markdown export, `# Title (Author)` then > quotes"
+    }
+
+    fn sniff(&self, file: &SourceFile) -> bool {
+        file.text().is_ok_and(|contents| {
+            let first_line = contents
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or_default();
+            let header = first_line.trim().strip_prefix("# ").and_then(|header| {
+                header
+                    .strip_suffix(')')
+                    .and_then(|header| header.rsplit_once(" ("))
+            });
+            header.is_some_and(|(title, author)| {
+                !title.trim().is_empty() && !author.trim().is_empty()
+            }) && contents.lines().any(|line| line.starts_with('>'))
+        })
+    }
+
+    fn parse(&self, file: &SourceFile, _options: &ImportOptions) -> Result<ParsedImport> {
+        let contents = file.text()?;
+        let (first_line, quotes) = contents.split_once('\n').unwrap_or((contents, ""));
+        let (title, author) = extract_yomu_title_author(first_line);
+        let mut parsed = ParsedImport::from(extract_yomu_flashcards(
+            &title,
+            &author,
+            quotes.to_string(),
+        )?);
+        if author.is_empty() {
+            parsed
+                .warnings
+                .push("no (author) after the title on the first line".to_string());
+        }
+        Ok(parsed)
+    }
+}
+
 pub fn extract_yomu_title_author(line: &str) -> (String, String) {
     // # An Inquiry into the Nature and Causes of the Wealth of Nations (Adam Smith)
     let mut title = String::new();
@@ -42,7 +954,11 @@ pub fn extract_yomu_title_author(line: &str) -> (String, String) {
     (title, author)
 }
 
-pub fn extract_yomu_flashcards(title: &str, file_contents: String) -> Result<Vec<FlashCard>> {
+pub fn extract_yomu_flashcards(
+    title: &str,
+    author: &str,
+    file_contents: String,
+) -> Result<Vec<FlashCard>> {
     let flash_cards = file_contents
         .split("---")
         .map(|part| {
@@ -56,23 +972,43 @@ pub fn extract_yomu_flashcards(title: &str, file_contents: String) -> Result<Vec
                 id: 0,
                 title: title.to_owned(),
                 body,
+                source_title: title.to_owned(),
+                author: author.to_owned(),
+                ..Default::default()
             }
         })
         .collect::<Vec<FlashCard>>();
     Ok(flash_cards)
 }
 
-///Import a file into the flashcards using the ReadEra exported format
-///Top line will be used as the title for flashcards, prefixed with a monotonically increasing
-///number
-pub fn import_read_era_quotes(fp: &str, conn: &Connection) -> Result<()> {
-    let file_contents = std::fs::read_to_string(fp)?;
-    //now we parse the file contents
-    extract_read_era_flash_cards(file_contents)?
-        .into_iter()
-        .try_for_each(|flashcard| -> Result<()> { save_flashcard_object(&flashcard, conn) })?;
+///ReadEra's exported quotes: title and author on the first two lines,
+///then the quotes split by `*****`
+pub struct ReadEraImporter;
 
-    Ok(())
+impl Importer for ReadEraImporter {
+    fn name(&self) -> &str {
+        "readera"
+    }
+
+    fn description(&self) -> &str {
+        "ReadEra quotes, title and author lines then quotes split by *****"
+    }
+
+    fn sniff(&self, file: &SourceFile) -> bool {
+        file.text()
+            .is_ok_and(|contents| contents.lines().any(|line| line.trim() == "*****"))
+    }
+
+    fn parse(&self, file: &SourceFile, _options: &ImportOptions) -> Result<ParsedImport> {
+        let mut parsed =
+            ParsedImport::from(extract_read_era_flash_cards(file.text()?.to_string())?);
+        if parsed.cards.first().is_some_and(|fc| fc.author.is_empty()) {
+            parsed
+                .warnings
+                .push("no author on the second line".to_string());
+        }
+        Ok(parsed)
+    }
 }
 
 ///Take readera style exported notes and extract them as flashcard objects
@@ -89,10 +1025,10 @@ fn extract_read_era_flash_cards(file_contents: String) -> Result<Vec<FlashCard>>
             for (j, line) in flash_card.split('\n').enumerate() {
                 //book title
                 if j == 0 {
-                    title = line.to_string();
+                    title = line.trim().to_string();
                 } else if j == 1 {
                     //author
-                    author = line.to_string(); //
+                    author = line.trim().to_string(); //
                 } else {
                     body.push_str(line);
                     body.push('\n');
@@ -103,44 +1039,2280 @@ fn extract_read_era_flash_cards(file_contents: String) -> Result<Vec<FlashCard>>
             body = flash_card.to_string();
         }
 
-        let mut flash_card_title = String::new();
-        flash_card_title.push_str(&title);
-        flash_card_title.push('\n');
-        flash_card_title.push_str(&author);
         let fc = FlashCard {
             id: 0, //this will be ignored
-            title: flash_card_title,
+            title: title.clone(),
             body,
+            source_title: title.clone(),
+            author: author.clone(),
+            ..Default::default()
         };
         fcards.push(fc);
     }
     Ok(fcards)
 }
 
-mod test {
-    #[allow(unused_imports)]
-    use crate::import::extract_read_era_flash_cards;
+///Column order of a Readwise export, which is also how we write them back out
+pub const READWISE_HEADER: [&str; 10] = [
+    "Highlight",
+    "Book Title",
+    "Book Author",
+    "Amazon Book ID",
+    "Note",
+    "Color",
+    "Tags",
+    "Location Type",
+    "Location",
+    "Highlighted at",
+];
 
-    #[test]
-    pub fn test_extract_flash_cards() {
-        let text = r"Test title
-            test author
-this is test line one.
-This is test line two.
-*****
-      second flascard line one.
-  second Flashcard line two.
-Second flashcard line 3..
-*****
-Third flashcard, foomy,
-Foombletoning
-Fumbleturning
-Sevenslurring
-Underscarring
---"
-        .to_string();
-        let flashcards = extract_read_era_flash_cards(text).unwrap();
-        assert_eq!(3, flashcards.len());
-        println!("flashcards! {:?}", flashcards);
+///One row of a Readwise export, columns we have no field for go in the metadata
+#[derive(Debug, Deserialize)]
+struct ReadwiseRow {
+    #[serde(rename = "Highlight")]
+    highlight: String,
+    #[serde(rename = "Book Title", default)]
+    book_title: String,
+    #[serde(rename = "Book Author", default)]
+    book_author: String,
+    #[serde(rename = "Amazon Book ID", default)]
+    amazon_book_id: String,
+    #[serde(rename = "Note", default)]
+    note: String,
+    #[serde(rename = "Color", default)]
+    color: String,
+    #[serde(rename = "Tags", default)]
+    tags: String,
+    #[serde(rename = "Location Type", default)]
+    location_type: String,
+    #[serde(rename = "Location", default)]
+    location: String,
+    #[serde(rename = "Highlighted at", default)]
+    highlighted_at: String,
+}
+
+///Take a Readwise CSV export, one highlight per row, the book title is the card title
+fn parse_readwise_csv(contents: String) -> Result<Vec<FlashCard>> {
+    let contents = contents.trim_start_matches('\u{feff}');
+    let mut reader = csv::Reader::from_reader(contents.as_bytes());
+    let mut cards = Vec::new();
+    for row in reader.deserialize() {
+        let row: ReadwiseRow = row?;
+        let metadata = [
+            ("amazon_book_id", row.amazon_book_id),
+            ("color", row.color),
+            ("location_type", row.location_type),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.trim().is_empty())
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+        cards.push(FlashCard {
+            title: row.book_title.clone(),
+            body: row.highlight,
+            tags: row.tags,
+            source_title: row.book_title,
+            author: row.book_author,
+            location: row.location,
+            note: row.note,
+            highlighted_at: row.highlighted_at,
+            metadata,
+            ..Default::default()
+        });
+    }
+    Ok(cards)
+}
+
+///The `.calibre_highlights` JSON the Calibre viewer exports. It doesn't name the book
+///unless a title and authors have been added at the top, otherwise the file name does
+#[derive(Debug, Deserialize)]
+struct CalibreExport {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(alias = "annotations")]
+    highlights: Vec<CalibreHighlight>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CalibreHighlight {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    removed: bool,
+    #[serde(default)]
+    highlighted_text: String,
+    #[serde(default)]
+    notes: String,
+    #[serde(default)]
+    timestamp: String,
+    ///An epubcfi, exact but not for reading
+    #[serde(default)]
+    pos: String,
+    #[serde(default)]
+    spine_name: String,
+    ///Chapter, and the sections it sits in
+    #[serde(default)]
+    toc_family_titles: Vec<String>,
+    style: Option<CalibreStyle>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CalibreStyle {
+    #[serde(default)]
+    which: String,
+}
+
+///Take Calibre's annotation export, one card per highlight. Bookmarks and
+///removed highlights are left out, the chapter is the location
+fn parse_calibre_annotations(contents: &str) -> Result<Vec<FlashCard>> {
+    let export: CalibreExport = serde_json::from_str(contents)?;
+    let author = export.authors.join(", ");
+    let cards = export
+        .highlights
+        .into_iter()
+        .filter(|h| h.kind == "highlight" && !h.removed)
+        .map(|h| {
+            let mut metadata = std::collections::BTreeMap::new();
+            if !h.pos.is_empty() {
+                metadata.insert("position".to_string(), h.pos);
+            }
+            if let Some(style) = h.style.filter(|style| !style.which.is_empty()) {
+                metadata.insert("color".to_string(), style.which);
+            }
+            let location = if h.toc_family_titles.is_empty() {
+                h.spine_name
+            } else {
+                metadata.insert("location_type".to_string(), "chapter".to_string());
+                h.toc_family_titles.join(" > ")
+            };
+            FlashCard {
+                title: export.title.clone(),
+                body: h.highlighted_text,
+                source_title: export.title.clone(),
+                author: author.clone(),
+                location,
+                note: h.notes,
+                highlighted_at: h.timestamp,
+                metadata,
+                ..Default::default()
+            }
+        })
+        .collect();
+    Ok(cards)
+}
+
+///Sections under headings of this level become cards, unless told otherwise
+pub const DEFAULT_HEADING_LEVEL: usize = 2;
+
+///What we use from the `---` block at the top of a note
+#[derive(Debug, Default)]
+struct FrontMatter {
+    title: String,
+    author: String,
+    tags: Vec<String>,
+}
+
+///Pull the front matter off the top of a note, handing back the rest.
+///Only simple `key: value` lines and `- item` lists are understood
+fn split_front_matter(contents: &str) -> (FrontMatter, &str) {
+    let mut front = FrontMatter::default();
+    let Some(rest) = contents
+        .strip_prefix("---\n")
+        .or_else(|| contents.strip_prefix("---\r\n"))
+    else {
+        return (front, contents);
+    };
+    let Some(end) = rest.find("\n---") else {
+        return (front, contents);
+    };
+    let body = rest[end + 4..]
+        .split_once('\n')
+        .map_or("", |(_, body)| body);
+    let mut key = "";
+    for line in rest[..end].lines() {
+        let unquote = |value: &str| value.trim().trim_matches(['"', '\'']).to_string();
+        if let Some(item) = line.trim_start().strip_prefix("- ") {
+            if matches!(key, "tags" | "tag") {
+                front
+                    .tags
+                    .push(unquote(item).trim_start_matches('#').to_string());
+            }
+            continue;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        key = name.trim();
+        match key {
+            "title" => front.title = unquote(value),
+            "author" => front.author = unquote(value),
+            "tags" | "tag" => front.tags.extend(
+                value
+                    .trim()
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split(',')
+                    .map(|tag| unquote(tag).trim_start_matches('#').to_string())
+                    .filter(|tag| !tag.is_empty()),
+            ),
+            _ => {}
+        }
+    }
+    (front, body)
+}
+
+///`## Heading` to its level and text
+fn markdown_heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let text = line[level..].strip_prefix(' ')?;
+    (1..=6).contains(&level).then_some((level, text.trim()))
+}
+
+///Obsidian spaced repetition cards written on one line, `question :: answer`,
+///or `question ::: answer` for a card each way round. The separator has to be
+///outside inline code, so `std::mem::swap` stays prose. `Question::Answer` with
+///no spaces works too, as long as the question is one word and the line isn't a
+///link, which leaves Dataview `key:: value` fields alone
+fn inline_cards(line: &str) -> Vec<(String, String)> {
+    let line = line.trim().trim_start_matches(['-', '*']).trim();
+    let outside_code = |separator: &str| {
+        line.match_indices(separator)
+            .map(|(i, _)| i)
+            .find(|i| line[..*i].matches('`').count().is_multiple_of(2))
+            .map(|i| (&line[..i], &line[i + separator.len()..]))
+    };
+    let unspaced = |separator: &str| {
+        outside_code(separator).filter(|(question, answer)| {
+            !question.is_empty()
+                && !question.contains(char::is_whitespace)
+                && !answer.starts_with(char::is_whitespace)
+                && !line.contains("://")
+        })
+    };
+    let (reversed, (question, answer)) = match outside_code(" ::: ") {
+        Some(split) => (true, split),
+        None => match outside_code(" :: ") {
+            Some(split) => (false, split),
+            None => match unspaced(":::") {
+                Some(split) => (true, split),
+                None => match unspaced("::") {
+                    Some(split) => (false, split),
+                    None => return Vec::new(),
+                },
+            },
+        },
+    };
+    let (question, answer) = (question.trim().to_string(), answer.trim().to_string());
+    if question.is_empty() || answer.is_empty() {
+        return Vec::new();
+    }
+    if reversed {
+        vec![(question.clone(), answer.clone()), (answer, question)]
+    } else {
+        vec![(question, answer)]
+    }
+}
+
+///Plain markdown notes: each heading at `heading_level` is a card title and the
+///section under it, down to the next heading at that level or above, is the body.
+///Inline `question :: answer` cards are picked out wherever they are, and front matter
+///tags go on every card. The note's title comes from front matter or its first `#`
+fn parse_markdown_notes(contents: &str, heading_level: usize) -> Vec<FlashCard> {
+    let (front, body) = split_front_matter(contents);
+    let mut source_title = front.title.clone();
+    let mut cards: Vec<(String, String)> = Vec::new();
+    let mut section: Option<(String, Vec<&str>)> = None;
+    let mut in_code = false;
+    for line in body.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        if !in_code {
+            if let Some((level, text)) = markdown_heading(line) {
+                if level == 1 && source_title.is_empty() {
+                    source_title = text.to_string();
+                }
+                if level <= heading_level {
+                    cards.extend(
+                        section
+                            .take()
+                            .map(|(title, lines)| (title, lines.join("\n"))),
+                    );
+                    if level == heading_level {
+                        section = Some((text.to_string(), Vec::new()));
+                    }
+                    continue;
+                }
+            }
+            cards.extend(inline_cards(line));
+        }
+        if let Some((_, lines)) = section.as_mut() {
+            lines.push(line);
+        }
+    }
+    cards.extend(section.map(|(title, lines)| (title, lines.join("\n"))));
+    let tags = front.tags.join(",");
+    cards
+        .into_iter()
+        .filter(|(_, body)| !body.trim().is_empty())
+        .map(|(title, body)| FlashCard {
+            title,
+            body,
+            tags: tags.clone(),
+            source_title: source_title.clone(),
+            author: front.author.clone(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+///Org properties that have a field of their own on the card, the rest go in the metadata
+pub const ORG_FIELD_PROPERTIES: [&str; 7] = [
+    "AUTHOR",
+    "SOURCE",
+    "LOCATION",
+    "URL",
+    "NOTE",
+    "HIGHLIGHTED",
+    "DECK",
+];
+
+///`** TODO [#A] Headline text :tag:other:` to its level, text and tags
+fn org_headline(line: &str) -> Option<(usize, String, Vec<String>)> {
+    let level = line.chars().take_while(|c| *c == '*').count();
+    let text = line[level..].strip_prefix(' ')?.trim();
+    if level == 0 {
+        return None;
+    }
+    let (text, tags) = match text.rsplit_once(' ') {
+        Some((head, tags)) if tags.len() > 1 && tags.starts_with(':') && tags.ends_with(':') => {
+            (head.trim(), org_tags(tags))
+        }
+        _ if text.len() > 1 && text.starts_with(':') && text.ends_with(':') => ("", org_tags(text)),
+        _ => (text, Vec::new()),
+    };
+    let text = ["TODO ", "DONE "]
+        .iter()
+        .find_map(|keyword| text.strip_prefix(keyword))
+        .unwrap_or(text);
+    let text = match text.strip_prefix("[#") {
+        Some(rest) if rest.get(1..3) == Some("] ") => &rest[3..],
+        _ => text,
+    };
+    Some((level, text.trim().to_string(), tags))
+}
+
+fn org_tags(tags: &str) -> Vec<String> {
+    tags.split(':')
+        .filter(|tag| !tag.trim().is_empty())
+        .map(|tag| tag.trim().to_string())
+        .collect()
+}
+
+///An org headline on its way to being a card
+struct OrgEntry<'a> {
+    title: String,
+    tags: Vec<String>,
+    properties: Vec<(String, String)>,
+    lines: Vec<&'a str>,
+}
+
+///Org files: every headline with text under it is a card, titled by the headline.
+///Tags are inherited from parent headlines and `#+FILETAGS`, and `PROPERTIES`
+///drawers are kept, the ones in ORG_FIELD_PROPERTIES in their fields
+fn parse_org(contents: &str) -> Vec<FlashCard> {
+    let (mut source_title, mut author) = (String::new(), String::new());
+    let mut file_tags = Vec::new();
+    //tags of the headlines above the current one
+    let mut parents: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut entries: Vec<OrgEntry> = Vec::new();
+    let mut in_drawer = false;
+    for line in contents.lines() {
+        if let Some((level, title, tags)) = org_headline(line) {
+            parents.retain(|(parent_level, _)| *parent_level < level);
+            let mut all_tags = file_tags.clone();
+            all_tags.extend(parents.iter().flat_map(|(_, tags)| tags.iter().cloned()));
+            all_tags.extend(tags.iter().cloned());
+            parents.push((level, tags));
+            entries.push(OrgEntry {
+                title,
+                tags: all_tags,
+                properties: Vec::new(),
+                lines: Vec::new(),
+            });
+            in_drawer = false;
+            continue;
+        }
+        let trimmed = line.trim();
+        let Some(entry) = entries.last_mut() else {
+            if let Some((keyword, value)) =
+                trimmed.strip_prefix("#+").and_then(|l| l.split_once(':'))
+            {
+                match keyword.to_uppercase().as_str() {
+                    "TITLE" => source_title = value.trim().to_string(),
+                    "AUTHOR" => author = value.trim().to_string(),
+                    "FILETAGS" => file_tags = org_tags(value.trim()),
+                    _ => {}
+                }
+            }
+            continue;
+        };
+        if trimmed.eq_ignore_ascii_case(":PROPERTIES:") && entry.lines.is_empty() {
+            in_drawer = true;
+        } else if in_drawer && trimmed.eq_ignore_ascii_case(":END:") {
+            in_drawer = false;
+        } else if in_drawer {
+            if let Some((key, value)) = trimmed.strip_prefix(':').and_then(|l| l.split_once(':')) {
+                entry
+                    .properties
+                    .push((key.to_string(), value.trim().to_string()));
+            }
+        } else if entry.lines.is_empty()
+            && ["SCHEDULED:", "DEADLINE:", "CLOSED:"]
+                .iter()
+                .any(|planning| trimmed.starts_with(planning))
+        {
+            continue;
+        } else {
+            entry.lines.push(line);
+        }
+    }
+    entries
+        .into_iter()
+        .filter(|entry| entry.lines.iter().any(|line| !line.trim().is_empty()))
+        .map(|entry| {
+            let mut fc = FlashCard {
+                title: entry.title,
+                body: entry.lines.join("\n"),
+                tags: entry.tags.join(","),
+                source_title: source_title.clone(),
+                author: author.clone(),
+                ..Default::default()
+            };
+            for (key, value) in entry.properties {
+                match key.to_uppercase().as_str() {
+                    "AUTHOR" => fc.author = value,
+                    "SOURCE" => fc.source_title = value,
+                    "LOCATION" => fc.location = value,
+                    "URL" => fc.source_url = value,
+                    "NOTE" => fc.note = value,
+                    "HIGHLIGHTED" => fc.highlighted_at = value,
+                    "DECK" => fc.deck = value,
+                    _ => {
+                        fc.metadata.insert(key.to_lowercase(), value);
+                    }
+                }
+            }
+            fc
+        })
+        .collect()
+}
+
+///Elements whose text we treat as a passage of their own
+const EPUB_BLOCKS: [&str; 16] = [
+    "p",
+    "div",
+    "blockquote",
+    "li",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "pre",
+    "dd",
+    "dt",
+    "td",
+    "section",
+    "body",
+];
+
+///Which markup to take passages from, `blockquote`, `.maxim` or `p.maxim`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkupSelector {
+    tag: Option<String>,
+    class: Option<String>,
+}
+
+impl std::str::FromStr for MarkupSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tag, class) = match s.trim().split_once('.') {
+            Some((tag, class)) => (tag, Some(class)),
+            None => (s.trim(), None),
+        };
+        if tag.is_empty() && class.is_none_or(str::is_empty) {
+            return Err("give a tag, a .class or both, eg p.maxim".to_string());
+        }
+        Ok(Self {
+            tag: Some(tag.to_lowercase()).filter(|tag| !tag.is_empty()),
+            class: class.map(str::to_string),
+        })
+    }
+}
+
+impl std::fmt::Display for MarkupSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.tag.as_deref().unwrap_or_default())?;
+        match &self.class {
+            Some(class) => write!(f, ".{}", class),
+            None => Ok(()),
+        }
+    }
+}
+
+impl MarkupSelector {
+    fn matches(&self, name: &str, attrs: &str) -> bool {
+        self.tag.as_ref().is_none_or(|tag| tag == name)
+            && self.class.as_ref().is_none_or(|class| {
+                html_attr(attrs, "class")
+                    .is_some_and(|classes| classes.split_whitespace().any(|c| c == class))
+            })
+    }
+}
+
+///DRM free epubs, going by the extension since they are zip files
+pub struct EpubImporter;
+
+impl Importer for EpubImporter {
+    fn name(&self) -> &str {
+        "epub"
+    }
+
+    fn description(&self) -> &str {
+        "DRM free epub books, passages picked with --match or --markup"
+    }
+
+    fn sniff(&self, file: &SourceFile) -> bool {
+        file.has_extension("epub")
+    }
+
+    fn parse(&self, file: &SourceFile, options: &ImportOptions) -> Result<ParsedImport> {
+        Ok(parse_epub(&file.bytes, options)?.into())
+    }
+
+    fn options_key(&self, options: &ImportOptions) -> String {
+        let mut key = Vec::new();
+        if let Some(pattern) = &options.passage_pattern {
+            key.push(format!("--match {}", pattern.as_str()));
+        }
+        if let Some(markup) = &options.passage_markup {
+            key.push(format!("--markup {}", markup));
+        }
+        key.join(" ")
+    }
+}
+
+///A paragraph of an epub, with the chapter heading it falls under
+struct EpubPassage {
+    chapter: String,
+    text: String,
+    ///Inside the markup we were asked for
+    marked: bool,
+}
+
+///Pull passages out of a DRM free epub: the paragraphs matching the pattern,
+///or wrapped in the markup, or both if both are given. Title and author come from the OPF
+fn parse_epub(bytes: &[u8], options: &ImportOptions) -> Result<Vec<FlashCard>> {
+    if options.passage_pattern.is_none() && options.passage_markup.is_none() {
+        bail!("say which passages to take from an epub with --match or --markup");
+    }
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+    let container = read_zip_text(&mut archive, "META-INF/container.xml")?;
+    let Some(opf_path) = html_pieces(&container)
+        .iter()
+        .find_map(|piece| match piece {
+            HtmlPiece::Open { name, attrs, .. } if name == "rootfile" => {
+                html_attr(attrs, "full-path")
+            }
+            _ => None,
+        })
+    else {
+        bail!("the epub doesn't say where its OPF file is");
+    };
+    let opf = read_zip_text(&mut archive, &opf_path)?;
+    let opf_dir = opf_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+
+    let (mut title, mut authors) = (String::new(), Vec::new());
+    let mut manifest = HashMap::new();
+    let mut spine = Vec::new();
+    let mut element = String::new();
+    for piece in html_pieces(&opf) {
+        match piece {
+            HtmlPiece::Open { name, attrs, .. } => {
+                match name.as_str() {
+                    "item" => {
+                        if let (Some(id), Some(href)) =
+                            (html_attr(attrs, "id"), html_attr(attrs, "href"))
+                        {
+                            manifest.insert(id, href);
+                        }
+                    }
+                    "itemref" => spine.extend(html_attr(attrs, "idref")),
+                    _ => {}
+                }
+                element = name;
+            }
+            HtmlPiece::Text(text) => {
+                let text = decode_html_entities(text).trim().to_string();
+                match element.as_str() {
+                    "dc:title" if title.is_empty() => title = text,
+                    "dc:creator" if !text.is_empty() => authors.push(text),
+                    _ => {}
+                }
+            }
+            HtmlPiece::Close(_) => element.clear(),
+        }
+    }
+    let author = authors.join(", ");
+
+    let mut cards = Vec::new();
+    for href in spine.iter().filter_map(|id| manifest.get(id)) {
+        let path = zip_path(opf_dir, href);
+        let xhtml = read_zip_text(&mut archive, &path)?;
+        for passage in epub_passages(&xhtml, options.passage_markup.as_ref()) {
+            let wanted = (options.passage_markup.is_none() || passage.marked)
+                && options
+                    .passage_pattern
+                    .as_ref()
+                    .is_none_or(|pattern| pattern.is_match(&passage.text));
+            if !wanted {
+                continue;
+            }
+            let mut metadata = std::collections::BTreeMap::new();
+            if !passage.chapter.is_empty() {
+                metadata.insert("location_type".to_string(), "chapter".to_string());
+            }
+            cards.push(FlashCard {
+                title: title.clone(),
+                body: passage.text,
+                source_title: title.clone(),
+                author: author.clone(),
+                location: passage.chapter,
+                metadata,
+                ..Default::default()
+            });
+        }
+    }
+    Ok(cards)
+}
+
+fn epub_passages(xhtml: &str, markup: Option<&MarkupSelector>) -> Vec<EpubPassage> {
+    let mut passages = Vec::new();
+    let mut chapter = String::new();
+    //open elements, and whether each matches the markup
+    let mut stack: Vec<(String, bool)> = Vec::new();
+    let mut text = String::new();
+    let mut marked = false;
+    let mut flush = |stack: &[(String, bool)], text: &mut String, marked: &mut bool| {
+        let cleaned = text
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let in_heading = stack
+            .iter()
+            .any(|(name, _)| matches!(name.as_str(), "h1" | "h2" | "h3"));
+        if in_heading && !cleaned.is_empty() {
+            chapter = cleaned;
+        } else if !cleaned.is_empty() {
+            passages.push(EpubPassage {
+                chapter: chapter.clone(),
+                text: cleaned,
+                marked: *marked,
+            });
+        }
+        text.clear();
+        *marked = false;
+    };
+    for piece in html_pieces(xhtml) {
+        match piece {
+            HtmlPiece::Text(chunk) => {
+                if stack.iter().any(|(name, _)| name == "head") {
+                    continue;
+                }
+                if !chunk.trim().is_empty() {
+                    marked |= stack.iter().any(|(_, matches)| *matches);
+                }
+                text.push_str(&decode_html_entities(chunk).replace('\n', " "));
+            }
+            HtmlPiece::Open { name, .. } if name == "br" => text.push('\n'),
+            HtmlPiece::Open {
+                name,
+                attrs,
+                self_closing,
+            } => {
+                if EPUB_BLOCKS.contains(&name.as_str()) {
+                    flush(&stack, &mut text, &mut marked);
+                }
+                if !self_closing && !matches!(name.as_str(), "img" | "hr" | "meta" | "link") {
+                    let matches = markup.is_some_and(|markup| markup.matches(&name, attrs));
+                    stack.push((name, matches));
+                }
+            }
+            HtmlPiece::Close(name) => {
+                if EPUB_BLOCKS.contains(&name.as_str()) {
+                    flush(&stack, &mut text, &mut marked);
+                }
+                if let Some(at) = stack.iter().rposition(|(open, _)| *open == name) {
+                    stack.truncate(at);
+                }
+            }
+        }
+    }
+    flush(&stack, &mut text, &mut marked);
+    passages
+}
+
+fn read_zip_text(
+    archive: &mut zip::ZipArchive<std::io::Cursor<&[u8]>>,
+    path: &str,
+) -> Result<String> {
+    let mut text = String::new();
+    archive.by_name(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+///Manifest hrefs are relative to the OPF file and percent encoded
+fn zip_path(dir: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut parts: Vec<&str> = dir.split('/').filter(|part| !part.is_empty()).collect();
+    for part in href.split('/') {
+        match part {
+            ".." => {
+                parts.pop();
+            }
+            "." | "" => {}
+            part => parts.push(part),
+        }
+    }
+    percent_decode(&parts.join("/"))
+}
+
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = text
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+///Apple's Core Data dates count seconds from 2001 rather than 1970
+const CORE_DATA_EPOCH: i64 = 978_307_200;
+
+///Import Apple Books highlights from copies of the `AEAnnotation` and `BKLibrary`
+///databases, remembered like any other file so running it again only brings in new ones
+pub fn import_apple_books(
+    annotations_db: &str,
+    library_db: &str,
+    force: bool,
+    conn: &Connection,
+) -> Result<()> {
+    let outcome = import_apple_books_file(annotations_db, library_db, force, conn);
+    let failed = outcome.is_err();
+    print_file_outcome(Path::new(annotations_db), outcome);
+    if failed {
+        bail!("Apple Books highlights failed to import");
+    }
+    Ok(())
+}
+
+fn import_apple_books_file(
+    annotations_db: &str,
+    library_db: &str,
+    force: bool,
+    conn: &Connection,
+) -> Result<FileOutcome> {
+    let hash = content_hash(&std::fs::read(annotations_db)?);
+    if !force && unchanged_since_import(Path::new(annotations_db), &hash, conn)? {
+        return Ok(FileOutcome::AlreadyImported);
+    }
+    let cards = parse_apple_books(annotations_db, library_db)?;
+    save_source_cards(Path::new(annotations_db), &hash, cards.into(), force, conn)
+}
+
+///Read the highlights out of the two databases, opened read only.
+///Books no longer in the library keep their asset id as a title
+pub fn parse_apple_books(annotations_db: &str, library_db: &str) -> Result<Vec<FlashCard>> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let library = Connection::open_with_flags(library_db, flags)?;
+    let mut stmt = library.prepare(
+        "SELECT ZASSETID, ZTITLE, ZAUTHOR FROM ZBKLIBRARYASSET WHERE ZASSETID IS NOT NULL",
+    )?;
+    let books = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                (
+                    row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                    row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                ),
+            ))
+        })?
+        .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+
+    let annotations = Connection::open_with_flags(annotations_db, flags)?;
+    let mut stmt = annotations.prepare(
+        "SELECT ZANNOTATIONASSETID, ZANNOTATIONSELECTEDTEXT, ZANNOTATIONNOTE,
+                ZANNOTATIONLOCATION, ZANNOTATIONCREATIONDATE, ZANNOTATIONSTYLE, ZFUTUREPROOFING5
+         FROM ZAEANNOTATION
+         WHERE ZANNOTATIONDELETED = 0 AND LENGTH(TRIM(ZANNOTATIONSELECTEDTEXT)) > 0
+         ORDER BY ZANNOTATIONASSETID, ZANNOTATIONCREATIONDATE",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+            row.get::<_, Option<f64>>(4)?,
+            row.get::<_, Option<i64>>(5)?,
+            row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+        ))
+    })?;
+    let mut cards = Vec::new();
+    for row in rows {
+        let (asset_id, body, note, position, created, style, chapter) = row?;
+        let (title, author) = books
+            .get(&asset_id)
+            .cloned()
+            .unwrap_or_else(|| (asset_id.clone(), String::new()));
+        let mut metadata = std::collections::BTreeMap::new();
+        metadata.insert("asset_id".to_string(), asset_id);
+        if !position.is_empty() {
+            metadata.insert("position".to_string(), position);
+        }
+        if let Some(color) = style.and_then(apple_books_color) {
+            metadata.insert("color".to_string(), color.to_string());
+        }
+        let location = if chapter.trim().is_empty() {
+            String::new()
+        } else {
+            metadata.insert("location_type".to_string(), "chapter".to_string());
+            chapter
+        };
+        cards.push(FlashCard {
+            title: title.clone(),
+            body,
+            source_title: title,
+            author,
+            location,
+            note,
+            highlighted_at: created
+                .map(|secs| format_unix_time(secs as i64 + CORE_DATA_EPOCH))
+                .unwrap_or_default(),
+            metadata,
+            ..Default::default()
+        });
+    }
+    Ok(cards)
+}
+
+fn apple_books_color(style: i64) -> Option<&'static str> {
+    match style {
+        0 => Some("underline"),
+        1 => Some("green"),
+        2 => Some("blue"),
+        3 => Some("yellow"),
+        4 => Some("pink"),
+        5 => Some("purple"),
+        _ => None,
+    }
+}
+
+///KOReader's JSON export, either one book with its `entries`,
+///or several under `documents` when everything is exported at once
+fn parse_koreader_json(contents: &str) -> Result<Vec<FlashCard>> {
+    let export: Value = serde_json::from_str(contents)?;
+    let books = match export.get("documents") {
+        Some(documents) => array_values(documents),
+        None if export.is_array() => array_values(&export),
+        None => vec![&export],
+    };
+    let mut cards = Vec::new();
+    for book in books {
+        let title = value_text(book.get("title"));
+        let author = value_text(book.get("author"));
+        for entry in array_values(book.get("entries").unwrap_or(&Value::Null)) {
+            cards.extend(koreader_card(&title, &author, entry, None));
+        }
+    }
+    Ok(cards)
+}
+
+///The `metadata.*.lua` sidecar KOReader keeps next to each book. Newer versions
+///keep a list of `annotations`, older ones `highlight` tables keyed by page
+fn parse_koreader_lua(contents: &str) -> Result<Vec<FlashCard>> {
+    let sidecar = parse_lua_table(contents)?;
+    let props = sidecar.get("doc_props").unwrap_or(&Value::Null);
+    let title = value_text(props.get("title"));
+    let author = value_text(props.get("authors")).replace('\n', ", ");
+    let mut cards = Vec::new();
+    if let Some(annotations) = sidecar.get("annotations") {
+        for entry in array_values(annotations) {
+            cards.extend(koreader_card(&title, &author, entry, None));
+        }
+    } else if let Some(Value::Object(pages)) = sidecar.get("highlight") {
+        let mut pages = pages.iter().collect::<Vec<_>>();
+        pages.sort_by_key(|(page, _)| page.parse::<usize>().unwrap_or(usize::MAX));
+        for (page, highlights) in pages {
+            for entry in array_values(highlights) {
+                cards.extend(koreader_card(&title, &author, entry, Some(page)));
+            }
+        }
+    }
+    Ok(cards)
+}
+
+///Where a highlight sits in the book and when it was made
+struct KOReaderPlace {
+    chapter: String,
+    page: String,
+    highlighted_at: String,
+}
+
+///One highlight from the JSON export or the sidecar, they mostly agree on names.
+///Bookmarks come without text and are left out
+fn koreader_card(
+    title: &str,
+    author: &str,
+    entry: &Value,
+    page: Option<&str>,
+) -> Option<FlashCard> {
+    let body = value_text(entry.get("text"));
+    if body.trim().is_empty() {
+        return None;
+    }
+    //epub sidecars keep an xpointer in page, pageno is the number we want
+    let page = match (entry.get("pageno"), entry.get("page"), page) {
+        (Some(pageno), _, _) => value_text(Some(pageno)),
+        (None, Some(Value::Number(page)), _) => page.to_string(),
+        (None, _, Some(page)) => page.to_string(),
+        _ => String::new(),
+    };
+    let highlighted_at = match entry.get("time").and_then(Value::as_i64) {
+        Some(secs) => format_unix_time(secs),
+        None => value_text(entry.get("datetime")),
+    };
+    let place = KOReaderPlace {
+        chapter: value_text(entry.get("chapter")),
+        page,
+        highlighted_at,
+    };
+    Some(koreader_flashcard(
+        title,
+        author,
+        body,
+        value_text(entry.get("note")),
+        place,
+    ))
+}
+
+fn koreader_flashcard(
+    title: &str,
+    author: &str,
+    body: String,
+    note: String,
+    place: KOReaderPlace,
+) -> FlashCard {
+    let mut metadata = std::collections::BTreeMap::new();
+    if !place.chapter.trim().is_empty() {
+        metadata.insert("chapter".to_string(), place.chapter);
+    }
+    if !place.page.is_empty() {
+        metadata.insert("location_type".to_string(), "page".to_string());
+    }
+    FlashCard {
+        title: title.to_owned(),
+        body,
+        source_title: title.to_owned(),
+        author: author.to_owned(),
+        location: place.page,
+        note,
+        highlighted_at: place.highlighted_at,
+        metadata,
+        ..Default::default()
+    }
+}
+
+///KOReader's markdown export: `# title`, `##### author`, `## chapter`,
+///then `### Page 12 @ datetime` above each highlight, with any note after a `---`
+fn parse_koreader_markdown(contents: &str) -> Vec<FlashCard> {
+    let mut cards = Vec::new();
+    let (mut title, mut author, mut chapter) = (String::new(), String::new(), String::new());
+    //where the highlight under the current page heading is, and its lines so far
+    let mut entry: Option<(KOReaderPlace, Vec<&str>)> = None;
+    for line in contents.lines() {
+        let heading = line
+            .split_once(' ')
+            .filter(|(hashes, _)| !hashes.is_empty() && hashes.chars().all(|c| c == '#'));
+        let Some((hashes, text)) = heading else {
+            if let Some((_, lines)) = entry.as_mut() {
+                lines.push(line);
+            }
+            continue;
+        };
+        if let Some((place, lines)) = entry.take() {
+            cards.extend(koreader_markdown_card(&title, &author, place, &lines));
+        }
+        let text = text.trim().to_string();
+        match hashes.len() {
+            1 => {
+                title = text;
+                author.clear();
+                chapter.clear();
+            }
+            2 => chapter = text,
+            3 => {
+                let (page, datetime) = text.split_once(" @ ").unwrap_or((&text, ""));
+                let place = KOReaderPlace {
+                    chapter: chapter.clone(),
+                    page: page.trim_start_matches("Page").trim().to_string(),
+                    highlighted_at: datetime.trim().to_string(),
+                };
+                entry = Some((place, Vec::new()));
+            }
+            _ => author = text,
+        }
+    }
+    if let Some((place, lines)) = entry {
+        cards.extend(koreader_markdown_card(&title, &author, place, &lines));
+    }
+    cards
+}
+
+fn koreader_markdown_card(
+    title: &str,
+    author: &str,
+    place: KOReaderPlace,
+    lines: &[&str],
+) -> Option<FlashCard> {
+    let (highlight, note) = match lines.iter().position(|line| line.trim() == "---") {
+        Some(i) => (&lines[..i], &lines[i + 1..]),
+        None => (lines, &[][..]),
+    };
+    let body = strip_emphasis(highlight.join("\n").trim());
+    if body.is_empty() {
+        return None;
+    }
+    let note = note.join("\n").trim().to_string();
+    Some(koreader_flashcard(title, author, body, note, place))
+}
+
+///KOReader wraps highlights in markup for their drawer style, eg `*text*` for italics
+fn strip_emphasis(text: &str) -> String {
+    for marker in ["**", "__", "~~", "==", "*", "_"] {
+        if let Some(inner) = text
+            .strip_prefix(marker)
+            .and_then(|rest| rest.strip_suffix(marker))
+        {
+            if !inner.trim().is_empty() {
+                return inner.to_string();
+            }
+        }
+    }
+    text.to_string()
+}
+
+///Strings as they are, numbers written out, anything else is empty
+fn value_text(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        _ => String::new(),
+    }
+}
+
+///Unix time as `YYYY-MM-DD HH:MM:SS` in UTC, the shape KOReader's own datetimes have
+fn format_unix_time(secs: i64) -> String {
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    //days since 1970 to a civil date, after Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+///Fields on each `#` delimited entry of a Moon+ Reader `.mrexpt` backup
+const MREXPT_TITLE: usize = 1;
+const MREXPT_PATH: usize = 2;
+const MREXPT_CHAPTER: usize = 4;
+const MREXPT_POSITION: usize = 6;
+const MREXPT_COLOR: usize = 8;
+const MREXPT_TIME: usize = 9;
+const MREXPT_NOTE: usize = 11;
+const MREXPT_TEXT: usize = 12;
+
+///Moon+ Reader's `.mrexpt` export: a short header, then one entry per highlight,
+///each starting with a `#` line and one field per line, newlines written as `<BR>`.
+///It has no author, and entries without text are bookmarks
+fn parse_moon_reader_mrexpt(contents: &str) -> Vec<FlashCard> {
+    let mut entries: Vec<Vec<&str>> = Vec::new();
+    for line in contents.lines() {
+        if line.trim() == "#" {
+            entries.push(Vec::new());
+        } else if let Some(entry) = entries.last_mut() {
+            entry.push(line);
+        }
+    }
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let field = |i: usize| {
+                entry
+                    .get(i)
+                    .map(|f| f.replace("<BR>", "\n"))
+                    .unwrap_or_default()
+            };
+            let body = field(MREXPT_TEXT);
+            if body.trim().is_empty() {
+                return None;
+            }
+            let mut title = field(MREXPT_TITLE);
+            if title.trim().is_empty() {
+                title = Path::new(&field(MREXPT_PATH))
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or_default()
+                    .to_string();
+            }
+            let mut metadata = std::collections::BTreeMap::new();
+            metadata.insert("location_type".to_string(), "chapter".to_string());
+            let position = field(MREXPT_POSITION).trim().to_string();
+            if !position.is_empty() {
+                metadata.insert("position".to_string(), position);
+            }
+            //colours are signed ARGB
+            if let Ok(argb) = field(MREXPT_COLOR).trim().parse::<i64>() {
+                metadata.insert("color".to_string(), format!("#{:06x}", argb & 0xff_ffff));
+            }
+            let highlighted_at = field(MREXPT_TIME)
+                .trim()
+                .parse::<i64>()
+                .map(|millis| format_unix_time(millis / 1000))
+                .unwrap_or_default();
+            Some(FlashCard {
+                title: title.clone(),
+                body,
+                source_title: title,
+                location: field(MREXPT_CHAPTER),
+                note: field(MREXPT_NOTE),
+                highlighted_at,
+                metadata,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+///What Moon+ Reader shares as text: title and author lines, `◆ chapter` headings,
+///and a `▪` before each highlight, with the note in brackets on its last line
+fn parse_moon_reader_shared(contents: &str) -> Vec<FlashCard> {
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let title = lines.next().unwrap_or_default().trim().to_string();
+    let mut author = String::new();
+    let mut chapter = String::new();
+    let mut highlights: Vec<(String, Vec<&str>)> = Vec::new();
+    for line in contents
+        .lines()
+        .skip_while(|line| line.trim() != title)
+        .skip(1)
+    {
+        let trimmed = line.trim();
+        if let Some(heading) = trimmed.strip_prefix('◆') {
+            chapter = heading.trim().to_string();
+        } else if let Some(text) = trimmed.strip_prefix('▪') {
+            highlights.push((chapter.clone(), vec![text.trim()]));
+        } else if let Some((_, text)) = highlights.last_mut() {
+            text.push(line);
+        } else if author.is_empty() && !trimmed.is_empty() {
+            author = trimmed.to_string();
+        }
+    }
+    highlights
+        .into_iter()
+        .map(|(chapter, mut text)| {
+            while text.last().is_some_and(|line| line.trim().is_empty()) {
+                text.pop();
+            }
+            let last = text.last().map(|line| line.trim()).unwrap_or_default();
+            let note = match last.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+                Some(note) if text.len() > 1 => {
+                    let note = note.to_string();
+                    text.pop();
+                    note
+                }
+                _ => String::new(),
+            };
+            let mut metadata = std::collections::BTreeMap::new();
+            if !chapter.is_empty() {
+                metadata.insert("location_type".to_string(), "chapter".to_string());
+            }
+            FlashCard {
+                title: title.clone(),
+                body: text.join("\n"),
+                source_title: title.clone(),
+                author: author.clone(),
+                location: chapter,
+                note,
+                metadata,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+///Block level elements we pay attention to in a Play Books export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlayBooksBlock {
+    DocTitle,
+    Title,
+    Subtitle,
+    Chapter,
+    Paragraph,
+}
+
+///The Google Doc Play Books keeps notes in, downloaded as HTML. Title and subtitle
+///are the book and author, headings are chapters, and each highlight is a table row:
+///highlight, note and date paragraphs, then a cell with the page number
+fn parse_play_books_html(contents: &str) -> Vec<FlashCard> {
+    let (mut title, mut author, mut chapter, mut doc_title) =
+        (String::new(), String::new(), String::new(), String::new());
+    let mut cards = Vec::new();
+    let mut block = None;
+    let mut text = String::new();
+    //cells of the current table row, each a list of paragraphs
+    let mut row: Option<Vec<Vec<String>>> = None;
+    for piece in html_pieces(contents) {
+        let (closing, name, attrs) = match piece {
+            HtmlPiece::Text(chunk) => {
+                text.push_str(chunk);
+                continue;
+            }
+            HtmlPiece::Open { name, attrs, .. } => (false, name, attrs),
+            HtmlPiece::Close(name) => (true, name, ""),
+        };
+        match (closing, name.as_str()) {
+            (false, "br") => text.push('\n'),
+            (false, "title") => block = Some(PlayBooksBlock::DocTitle),
+            (false, "p") => {
+                let classes = html_classes(attrs);
+                block = Some(if classes.iter().any(|c| c == "title") {
+                    PlayBooksBlock::Title
+                } else if classes.iter().any(|c| c == "subtitle") {
+                    PlayBooksBlock::Subtitle
+                } else {
+                    PlayBooksBlock::Paragraph
+                });
+            }
+            (false, "h1") => block = Some(PlayBooksBlock::Title),
+            (false, "h2" | "h3" | "h4") => block = Some(PlayBooksBlock::Chapter),
+            (false, "tr") => row = Some(Vec::new()),
+            (false, "td") => row.iter_mut().for_each(|cells| cells.push(Vec::new())),
+            (true, "title" | "p" | "h1" | "h2" | "h3" | "h4") => {
+                let finished = decode_html_entities(&text).trim().to_string();
+                match block.take() {
+                    Some(PlayBooksBlock::DocTitle) => doc_title = finished,
+                    Some(PlayBooksBlock::Title) if title.is_empty() => title = finished,
+                    Some(PlayBooksBlock::Subtitle) if author.is_empty() => author = finished,
+                    Some(PlayBooksBlock::Chapter) => chapter = finished,
+                    Some(PlayBooksBlock::Paragraph) if !finished.is_empty() => {
+                        if let Some(cell) = row.as_mut().and_then(|cells| cells.last_mut()) {
+                            cell.push(finished);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            (true, "tr") => {
+                if let Some(cells) = row.take() {
+                    cards.extend(play_books_card(&chapter, cells));
+                }
+            }
+            _ => {}
+        }
+        if !matches!(
+            name.as_str(),
+            "span" | "a" | "b" | "i" | "em" | "strong" | "br"
+        ) {
+            text.clear();
+        }
+    }
+    if title.is_empty() {
+        title = doc_title
+            .trim_start_matches("Notes from")
+            .trim()
+            .trim_matches('"')
+            .to_string();
+    }
+    for fc in &mut cards {
+        fc.title = title.clone();
+        fc.source_title = title.clone();
+        fc.author = author.clone();
+    }
+    cards
+}
+
+///One table row of a Play Books export, before the title and author are known
+fn play_books_card(chapter: &str, cells: Vec<Vec<String>>) -> Option<FlashCard> {
+    let mut cells = cells.into_iter();
+    let mut paragraphs = cells.next()?;
+    let page = cells.next().unwrap_or_default().join(" ");
+    let highlighted_at = match paragraphs.last() {
+        Some(last) if paragraphs.len() > 1 && looks_like_date(last) => paragraphs.pop(),
+        _ => None,
+    };
+    if paragraphs.is_empty() {
+        return None;
+    }
+    let body = paragraphs.remove(0);
+    let mut metadata = std::collections::BTreeMap::new();
+    if !chapter.is_empty() {
+        metadata.insert("chapter".to_string(), chapter.to_string());
+    }
+    if !page.is_empty() {
+        metadata.insert("location_type".to_string(), "page".to_string());
+    }
+    Some(FlashCard {
+        body,
+        location: page,
+        note: paragraphs.join("\n"),
+        highlighted_at: highlighted_at.unwrap_or_default(),
+        metadata,
+        ..Default::default()
+    })
+}
+
+///Dates the way Play Books writes them, eg `April 1, 2023`
+fn looks_like_date(text: &str) -> bool {
+    const MONTHS: [&str; 12] = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    matches!(words.as_slice(), [month, day, year]
+        if MONTHS.contains(month)
+            && day.trim_end_matches(',').parse::<u8>().is_ok()
+            && year.len() == 4
+            && year.parse::<u16>().is_ok())
+}
+
+///A piece of an HTML or XML document, as much as the importers need.
+///Comments, doctypes and the insides of style and script elements are left out
+#[derive(Debug, PartialEq, Eq)]
+enum HtmlPiece<'a> {
+    Open {
+        name: String,
+        attrs: &'a str,
+        self_closing: bool,
+    },
+    Close(String),
+    Text(&'a str),
+}
+
+fn html_pieces(html: &str) -> Vec<HtmlPiece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        if start > 0 {
+            pieces.push(HtmlPiece::Text(&rest[..start]));
+        }
+        rest = &rest[start..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            return pieces;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with(['!', '?']) {
+            continue;
+        }
+        let (name, attrs) = tag
+            .trim_start_matches('/')
+            .split_once(char::is_whitespace)
+            .unwrap_or((tag.trim_start_matches('/'), ""));
+        let name = name.trim_end_matches('/').to_lowercase();
+        if tag.starts_with('/') {
+            pieces.push(HtmlPiece::Close(name));
+            continue;
+        }
+        if matches!(name.as_str(), "style" | "script") {
+            rest = rest.find("</").map_or("", |end| &rest[end..]);
+        }
+        pieces.push(HtmlPiece::Open {
+            name,
+            attrs,
+            self_closing: tag.ends_with('/'),
+        });
+    }
+    if !rest.is_empty() {
+        pieces.push(HtmlPiece::Text(rest));
+    }
+    pieces
+}
+
+///The value of an attribute, in either kind of quotes
+fn html_attr(attrs: &str, name: &str) -> Option<String> {
+    let mut rest = attrs;
+    while let Some(at) = rest.find(name) {
+        let before = rest[..at].chars().last();
+        let after = rest[at + name.len()..].trim_start();
+        rest = &rest[at + name.len()..];
+        if before.is_some_and(|c| !c.is_whitespace()) {
+            continue;
+        }
+        let Some(value) = after.strip_prefix('=').map(str::trim_start) else {
+            continue;
+        };
+        let quote = value.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+        return value[1..]
+            .split_once(quote)
+            .map(|(value, _)| decode_html_entities(value));
+    }
+    None
+}
+
+fn html_classes(attrs: &str) -> Vec<String> {
+    html_attr(attrs, "class")
+        .map(|classes| classes.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn decode_html_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 8)
+            .map(|end| &rest[1..end + 1]);
+        let replacement = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        });
+        match (entity, replacement) {
+            (Some(entity), Some(c)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use crate::import::{extract_read_era_flash_cards, Importer};
+
+    ///Name of the importer that would take this text
+    #[allow(dead_code)]
+    fn detected_format(text: &str) -> Option<String> {
+        use crate::import::{detect_importer, SourceFile};
+
+        let file = SourceFile {
+            path: Default::default(),
+            bytes: text.as_bytes().to_vec(),
+        };
+        detect_importer(&file).map(|importer| importer.name().to_string())
+    }
+
+    #[test]
+    pub fn test_extract_flash_cards() {
+        let text = r"Test title
+            test author
+this is test line one.
+This is test line two.
+*****
+      second flascard line one.
+  second Flashcard line two.
+Second flashcard line 3..
+*****
+Third flashcard, foomy,
+Foombletoning
+Fumbleturning
+Sevenslurring
+Underscarring
+--"
+        .to_string();
+        let flashcards = extract_read_era_flash_cards(text).unwrap();
+        assert_eq!(3, flashcards.len());
+        assert_eq!("Test title", flashcards[2].source_title);
+        assert_eq!("test author", flashcards[2].author);
+        println!("flashcards! {:?}", flashcards);
+    }
+
+    #[test]
+    pub fn test_yomu_blank_sections_are_skipped() {
+        use crate::db::{fetch_initial_flash_card_count, init_table};
+        use crate::import::{extract_yomu_flashcards, number_cards, save_numbered_flashcards};
+        use rusqlite::Connection;
+
+        let text = r"
+> first quote
+> carries on
+
+---
+
+> second quote
+
+---
+
+"
+        .to_string();
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        let cards = extract_yomu_flashcards("Title", "Author", text).unwrap();
+        assert_eq!(3, cards.len());
+        let summary = save_numbered_flashcards(number_cards(cards), &conn).unwrap();
+        assert_eq!(2, summary.imported);
+        assert_eq!(vec![(3, "empty body".to_string())], summary.skipped);
+        assert_eq!(2, fetch_initial_flash_card_count(false, &conn).unwrap());
+    }
+
+    #[test]
+    pub fn test_apply_choices() {
+        use crate::import::apply_choices;
+        use std::collections::BTreeSet;
+
+        let numbers = (1..=6).collect::<BTreeSet<_>>();
+        let mut chosen = numbers.clone();
+        assert!(apply_choices("2, 4-5", &numbers, &mut chosen).is_empty());
+        assert_eq!(vec![1, 3, 6], chosen.iter().copied().collect::<Vec<_>>());
+        //a huge range only puts back what the preview has
+        assert!(apply_choices("+1-4000000000", &numbers, &mut chosen).is_empty());
+        assert_eq!(numbers, chosen);
+        assert_eq!(2, apply_choices("9-2,+x", &numbers, &mut chosen).len());
+    }
+
+    #[test]
+    pub fn test_chosen_cards_keep_their_preview_numbers() {
+        use crate::db::{init_table, FlashCard};
+        use crate::import::save_numbered_flashcards;
+        use rusqlite::Connection;
+
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        let card = |body: &str| FlashCard {
+            title: "Title".to_string(),
+            body: body.to_string(),
+            ..Default::default()
+        };
+        //cards 1 and 3 were left out of the preview
+        let summary =
+            save_numbered_flashcards(vec![(2, card("kept")), (4, card(""))], &conn).unwrap();
+        assert_eq!(1, summary.imported);
+        assert_eq!(vec![(4, "empty body".to_string())], summary.skipped);
+    }
+
+    #[test]
+    pub fn test_import_thousands_of_yomu_quotes() {
+        use crate::db::{fetch_initial_flash_card_count, init_table};
+        use crate::import::{import_source_file, FileOutcome};
+        use rusqlite::Connection;
+
+        let entries = 5000;
+        let mut text = String::from("# Generated Aphorisms (Test Author)\n\n");
+        for i in 0..entries {
+            text.push_str(&format!(
+                "> Aphorism number {}\n> second line\n\n---\n\n",
+                i
+            ));
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let fp = dir.path().join("yomu.md");
+        std::fs::write(&fp, text).unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        let outcome = import_source_file(&fp, &Default::default(), &conn).unwrap();
+        let FileOutcome::Imported(summary) = outcome else {
+            panic!("yomu file was not imported");
+        };
+
+        assert_eq!(entries, summary.imported);
+        assert_eq!(
+            entries,
+            fetch_initial_flash_card_count(false, &conn).unwrap()
+        );
+    }
+
+    #[test]
+    pub fn test_failed_import_rolls_back() {
+        use crate::db::{fetch_initial_flash_card_count, init_table, FlashCard};
+        use crate::import::{number_cards, save_numbered_flashcards};
+        use rusqlite::Connection;
+
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        //make the database choke part way through
+        conn.execute(
+            "CREATE TRIGGER choke BEFORE INSERT ON flashcard WHEN NEW.body = 'boom'
+             BEGIN SELECT RAISE(ABORT, 'boom'); END",
+            [],
+        )
+        .unwrap();
+        let cards = ["one", "two", "boom", "four"]
+            .iter()
+            .map(|body| FlashCard {
+                title: "Title".to_string(),
+                body: body.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        assert!(save_numbered_flashcards(number_cards(cards), &conn).is_err());
+        assert_eq!(0, fetch_initial_flash_card_count(false, &conn).unwrap());
+    }
+
+    #[test]
+    pub fn test_directory_import_skips_known_files() {
+        use crate::db::{fetch_initial_flash_card_count, init_table};
+        use crate::import::{discover_files, import_source_file, FileOutcome, ImportOptions};
+        use rusqlite::Connection;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("yomu.md"), "# Book (Author)\n\n>quote\n\n---\n").unwrap();
+        std::fs::write(
+            dir.join("nested/readera.txt"),
+            "Book\nAuthor\nfirst\n*****\nsecond\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("cover.png"), "not a highlight").unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        let files = discover_files(&[dir.display().to_string()]).unwrap();
+        assert_eq!(2, files.len());
+        for file in &files {
+            let outcome = import_source_file(file, &ImportOptions::default(), &conn).unwrap();
+            assert!(matches!(outcome, FileOutcome::Imported(_)));
+        }
+        let total = fetch_initial_flash_card_count(false, &conn).unwrap();
+        for file in &files {
+            let outcome = import_source_file(file, &ImportOptions::default(), &conn).unwrap();
+            assert!(matches!(outcome, FileOutcome::AlreadyImported));
+        }
+        assert_eq!(3, total);
+        assert_eq!(total, fetch_initial_flash_card_count(false, &conn).unwrap());
+    }
+
+    #[test]
+    pub fn test_changed_file_only_imports_new_cards() {
+        use crate::db::{fetch_initial_flash_card_count, init_table};
+        use crate::import::{import_source_file, FileOutcome, ImportOptions};
+        use rusqlite::Connection;
+
+        let dir = tempfile::tempdir().unwrap();
+        let fp = dir.path().join("changed.md");
+        std::fs::write(&fp, "# Book (Author)\n\n>one\n\n---\n").unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        import_source_file(&fp, &ImportOptions::default(), &conn).unwrap();
+
+        std::fs::write(&fp, "# Book (Author)\n\n>one\n\n---\n\n>two\n\n---\n").unwrap();
+        let outcome = import_source_file(&fp, &ImportOptions::default(), &conn).unwrap();
+        let FileOutcome::Imported(summary) = outcome else {
+            panic!("changed file was not imported");
+        };
+        assert_eq!(1, summary.imported);
+        assert_eq!(1, summary.already_imported);
+        assert_eq!(2, fetch_initial_flash_card_count(false, &conn).unwrap());
+    }
+
+    #[test]
+    pub fn test_failed_files_fail_the_import() {
+        use crate::db::init_table;
+        use crate::import::{import_paths, ImportOptions};
+        use rusqlite::Connection;
+
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.md");
+        std::fs::write(&good, "# Book (Author)\n\n>one\n\n---\n").unwrap();
+        let good = good.display().to_string();
+        let missing = dir.path().join("missing.md").display().to_string();
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        let options = ImportOptions::default();
+
+        assert!(import_paths(&[good.clone(), missing.clone()], &options, &conn).is_err());
+        //unchanged since last time is still a success
+        assert!(import_paths(std::slice::from_ref(&good), &options, &conn).is_ok());
+        assert!(import_paths(&[missing], &options, &conn).is_err());
+    }
+
+    #[test]
+    pub fn test_cards_roll_back_if_the_source_is_not_recorded() {
+        use crate::db::{fetch_initial_flash_card_count, init_table};
+        use crate::import::{import_source_file, ImportOptions};
+        use rusqlite::Connection;
+
+        let dir = tempfile::tempdir().unwrap();
+        let fp = dir.path().join("unrecorded.md");
+        std::fs::write(&fp, "# Book (Author)\n\n>one\n\n---\n").unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        conn.execute(
+            "CREATE TRIGGER choke BEFORE INSERT ON import_source
+             BEGIN SELECT RAISE(ABORT, 'boom'); END",
+            [],
+        )
+        .unwrap();
+        let outcome = import_source_file(&fp, &ImportOptions::default(), &conn);
+        assert!(outcome.is_err());
+        assert_eq!(0, fetch_initial_flash_card_count(false, &conn).unwrap());
+    }
+
+    #[test]
+    pub fn test_readwise_round_trip() {
+        use crate::db::{find_flashcards, init_table, CardSelection};
+        use crate::export::export_readwise_csv;
+        use crate::import::{number_cards, read_source_file, save_numbered_flashcards};
+        use rusqlite::Connection;
+
+        let text = "Highlight,Book Title,Book Author,Amazon Book ID,Note,Color,Tags,Location Type,Location,Highlighted at
+\"It was the best of times, it was the worst of times\",A Tale of Two Cities,Charles Dickens,B0001,opening line,yellow,\"classic,opening\",location,12,2023-04-01 10:00:00+00:00
+\"Multi
+line\",Walden,Henry David Thoreau,,,,,page,7,
+";
+        let dir = tempfile::tempdir().unwrap();
+        let fp = dir.path().join("readwise.csv");
+        std::fs::write(&fp, text).unwrap();
+        let cards = read_source_file(&fp, &Default::default())
+            .unwrap()
+            .unwrap()
+            .cards;
+        assert_eq!(2, cards.len());
+        assert_eq!("A Tale of Two Cities", cards[0].title);
+        assert_eq!("Charles Dickens", cards[0].author);
+        assert_eq!("opening line", cards[0].note);
+        assert_eq!("yellow", cards[0].metadata["color"]);
+        assert_eq!("Multi\nline", cards[1].body);
+
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        save_numbered_flashcards(number_cards(cards), &conn).unwrap();
+        let selection = CardSelection {
+            query: Some(String::new()),
+            ..Default::default()
+        };
+        let saved = find_flashcards(&selection, &conn).unwrap();
+        export_readwise_csv(&saved, fp.to_str().unwrap()).unwrap();
+        let exported = std::fs::read_to_string(&fp).unwrap();
+        assert_eq!(text, exported);
+    }
+
+    #[test]
+    pub fn test_koreader_exports() {
+        use crate::import::ImportFormat;
+
+        let json = r#"{
+            "title": "Meditations", "author": "Marcus Aurelius", "number_of_pages": 200,
+            "entries": [
+                {"chapter": "Book Two", "page": 14, "time": 1680343200,
+                 "text": "Begin the morning by saying to thyself", "note": "every day", "sort": "highlight"}
+            ]
+        }"#;
+        let markdown = "# Meditations
+##### Marcus Aurelius
+
+## Book Two
+### Page 14 @ 01 April 2023 10:00:00 AM
+*Begin the morning by saying to thyself*
+
+---
+every day
+
+### Page 15 @ 01 April 2023 10:05:00 AM
+Second highlight
+";
+        let lua = r#"-- we can read Lua syntax here!
+return {
+    ["annotations"] = {
+        [1] = {
+            ["chapter"] = "Book Two",
+            ["datetime"] = "2023-04-01 10:00:00",
+            ["note"] = "every day",
+            ["page"] = "/body/DocFragment[4]/body/p[3]/text().0",
+            ["pageno"] = 14,
+            ["text"] = "Begin the morning by saying to thyself",
+        },
+        [2] = {
+            ["chapter"] = "Book Two",
+            ["page"] = "/body/DocFragment[4]",
+        },
+    },
+    ["doc_props"] = {
+        ["authors"] = "Marcus Aurelius",
+        ["title"] = "Meditations",
+    },
+}"#;
+        for (contents, format) in [
+            (json, ImportFormat::KOReaderJson),
+            (markdown, ImportFormat::KOReaderMarkdown),
+            (lua, ImportFormat::KOReaderLua),
+        ] {
+            assert_eq!(Some(format.name()), detected_format(contents).as_deref());
+            let cards = format
+                .parse_text(contents.to_string(), &Default::default())
+                .unwrap();
+            let fc = &cards[0];
+            assert_eq!("Meditations", fc.title);
+            assert_eq!("Marcus Aurelius", fc.author);
+            assert_eq!("Begin the morning by saying to thyself", fc.body);
+            assert_eq!("every day", fc.note);
+            assert_eq!("14", fc.location);
+            assert_eq!("Book Two", fc.metadata["chapter"]);
+            assert!(!fc.highlighted_at.is_empty());
+        }
+        let json_cards = ImportFormat::KOReaderJson
+            .parse_text(json.to_string(), &Default::default())
+            .unwrap();
+        assert_eq!("2023-04-01 10:00:00", json_cards[0].highlighted_at);
+        let markdown_cards = ImportFormat::KOReaderMarkdown
+            .parse_text(markdown.to_string(), &Default::default())
+            .unwrap();
+        assert_eq!(2, markdown_cards.len());
+        assert_eq!("15", markdown_cards[1].location);
+        //the bookmark has no text
+        let lua_cards = ImportFormat::KOReaderLua
+            .parse_text(lua.to_string(), &Default::default())
+            .unwrap();
+        assert_eq!(1, lua_cards.len());
+    }
+
+    #[test]
+    pub fn test_calibre_annotations() {
+        use crate::import::read_source_file;
+
+        let text = r#"{"type": "calibre_highlights", "version": 1, "highlights": [
+            {"type": "highlight", "uuid": "a1", "highlighted_text": "All happy families are alike",
+             "notes": "famous", "timestamp": "2023-04-01T10:00:00.000Z",
+             "pos_type": "epubcfi", "pos": "epubcfi(/6/4!/4/2/1:0)", "spine_index": 1,
+             "spine_name": "part1.xhtml", "toc_family_titles": ["Part One", "Chapter 1"],
+             "style": {"kind": "color", "type": "builtin", "which": "yellow"}},
+            {"type": "bookmark", "title": "where I was", "pos": "epubcfi(/6/8)"},
+            {"type": "highlight", "uuid": "a2", "removed": true}
+        ]}"#;
+        assert_eq!(Some("calibre"), detected_format(text).as_deref());
+        let dir = tempfile::tempdir().unwrap();
+        let fp = dir.path().join("Anna Karenina.calibre_highlights");
+        std::fs::write(&fp, text).unwrap();
+        let cards = read_source_file(&fp, &Default::default())
+            .unwrap()
+            .unwrap()
+            .cards;
+
+        assert_eq!(1, cards.len());
+        let fc = &cards[0];
+        assert!(fc.title.starts_with("Anna Karenina"));
+        assert_eq!("All happy families are alike", fc.body);
+        assert_eq!("famous", fc.note);
+        assert_eq!("Part One > Chapter 1", fc.location);
+        assert_eq!("epubcfi(/6/4!/4/2/1:0)", fc.metadata["position"]);
+        assert_eq!("yellow", fc.metadata["color"]);
+    }
+
+    #[test]
+    pub fn test_moon_reader_and_play_books() {
+        use crate::import::ImportFormat;
+
+        let mrexpt = include_str!("../resources/fixtures/moon_reader.mrexpt");
+        assert_eq!(Some("moon-reader"), detected_format(mrexpt).as_deref());
+        let cards = ImportFormat::MoonReader
+            .parse_text(mrexpt.to_string(), &Default::default())
+            .unwrap();
+        assert_eq!(2, cards.len());
+        assert_eq!("The Count of Monte Cristo", cards[0].title);
+        assert_eq!(
+            "All human wisdom is contained in these two words,\nWait and Hope.",
+            cards[0].body
+        );
+        assert_eq!("wait and hope", cards[0].note);
+        assert_eq!("3", cards[0].location);
+        assert_eq!("#ffff00", cards[0].metadata["color"]);
+        assert_eq!("2023-04-01 10:00:00", cards[0].highlighted_at);
+        assert_eq!("", cards[1].note);
+        assert_eq!("1520", cards[0].metadata["position"]);
+        let unplaced = mrexpt.replace("\n880\n", "\n\n");
+        let cards = ImportFormat::MoonReader
+            .parse_text(unplaced, &Default::default())
+            .unwrap();
+        assert!(!cards[1].metadata.contains_key("position"));
+
+        let shared = include_str!("../resources/fixtures/moon_reader_shared.txt");
+        assert_eq!(
+            Some("moon-reader-shared"),
+            detected_format(shared).as_deref()
+        );
+        let cards = ImportFormat::MoonReaderShared
+            .parse_text(shared.to_string(), &Default::default())
+            .unwrap();
+        assert_eq!(3, cards.len());
+        assert_eq!("The Count of Monte Cristo", cards[0].title);
+        assert_eq!("Alexandre Dumas", cards[0].author);
+        assert_eq!(
+            "All human wisdom is contained in these two words,\nWait and Hope.",
+            cards[0].body
+        );
+        assert_eq!("wait and hope", cards[0].note);
+        assert_eq!("Chapter 4", cards[1].location);
+        assert_eq!("Chapter 8", cards[2].location);
+        let bullets = "# Packing\n\nFor the trip\nbefore Friday\n\n▪ passport\n▪ charger\n";
+        assert_eq!(Some("markdown"), detected_format(bullets).as_deref());
+
+        let html = include_str!("../resources/fixtures/play_books.html");
+        assert_eq!(Some("play-books"), detected_format(html).as_deref());
+        let cards = ImportFormat::PlayBooks
+            .parse_text(html.to_string(), &Default::default())
+            .unwrap();
+        assert_eq!(2, cards.len());
+        assert_eq!("Middlemarch", cards[0].title);
+        assert_eq!("George Eliot", cards[0].author);
+        assert_eq!(
+            "Here and there is born a Saint Theresa, foundress of nothing.",
+            cards[0].body
+        );
+        assert_eq!("the famous opening", cards[0].note);
+        assert_eq!("April 1, 2023", cards[0].highlighted_at);
+        assert_eq!("3", cards[0].location);
+        assert_eq!("Prelude", cards[0].metadata["chapter"]);
+        assert!(cards[1].body.contains("poor dress & plain garments"));
+        assert_eq!("", cards[1].note);
+        assert_eq!("Chapter I", cards[1].metadata["chapter"]);
+    }
+
+    #[test]
+    pub fn test_apple_books_databases() {
+        use crate::import::parse_apple_books;
+
+        let cards = parse_apple_books(
+            "resources/fixtures/AEAnnotation.sqlite",
+            "resources/fixtures/BKLibrary.sqlite",
+        )
+        .unwrap();
+        //one highlight is deleted and one annotation is a bookmark
+        assert_eq!(3, cards.len());
+        let fc = &cards[0];
+        assert_eq!("A Tale of Two Cities", fc.title);
+        assert_eq!("Charles Dickens", fc.author);
+        assert_eq!(
+            "It was the best of times, it was the worst of times",
+            fc.body
+        );
+        assert_eq!("the whole plot in a line", fc.note);
+        assert_eq!("Book the First", fc.location);
+        assert_eq!("yellow", fc.metadata["color"]);
+        assert_eq!("2023-04-02 10:00:00", fc.highlighted_at);
+        assert_eq!("Chapter III", cards[1].location);
+        //not in the library copy
+        assert_eq!("Z9Y8", cards[2].title);
+        assert_eq!("", cards[2].author);
+    }
+
+    #[test]
+    pub fn test_markdown_notes() {
+        use crate::import::{inline_cards, parse_markdown_notes};
+
+        let text = "---
+title: Rust notes
+tags: [rust, \"#programming\"]
+---
+# Ownership
+
+Intro that isn't in a section.
+
+## Borrowing
+You can have one mutable reference
+or any number of shared ones.
+
+### Detail
+Still part of borrowing.
+
+- What moves a value? :: Assignment, passing by value
+Lifetime ::: How long a reference is valid
+Use `std::mem::swap` here, or `a :: b` in Haskell.
+
+```rust
+let not_a_card = \"a::b\";
+```
+
+## Empty
+
+## Slices
+A view into a sequence.
+";
+        assert_eq!(Some("markdown"), detected_format(text).as_deref());
+        let cards = parse_markdown_notes(text, 2);
+        let titles = cards.iter().map(|fc| fc.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "What moves a value?",
+                "Lifetime",
+                "How long a reference is valid",
+                "Borrowing",
+                "Slices"
+            ],
+            titles
+        );
+        assert_eq!("Assignment, passing by value", cards[0].body);
+        assert!(cards[3]
+            .body
+            .contains("### Detail\nStill part of borrowing."));
+        assert!(cards[3].body.contains("a::b"));
+        assert!(cards[3].body.contains("Lifetime ::: How long"));
+        assert!(cards[3].body.contains("`std::mem::swap`"));
+        assert!(cards.iter().all(|fc| fc.tags == "rust,programming"));
+        assert!(cards.iter().all(|fc| fc.source_title == "Rust notes"));
+
+        assert_eq!(
+            vec![("Ownership".to_string(), "Moves".to_string())],
+            inline_cards("Ownership::Moves")
+        );
+        assert_eq!(2, inline_cards("- Borrow:::Reference").len());
+        assert!(inline_cards("status:: reading").is_empty());
+        assert!(inline_cards("What is it::An answer").is_empty());
+        assert!(inline_cards("::answer").is_empty());
+        assert!(inline_cards("see http://[::1]:8080/").is_empty());
+
+        //one card for the whole note
+        let cards = parse_markdown_notes(text, 1);
+        assert_eq!(4, cards.len());
+        assert_eq!("Ownership", cards[3].title);
+    }
+
+    #[test]
+    pub fn test_org_round_trip() {
+        use crate::export::export_org;
+        use crate::import::{detect_importer, ImportFormat, ImportOptions, SourceFile};
+
+        let text = "#+TITLE: Stoics
+#+FILETAGS: :philosophy:
+* Seneca :latin:
+** TODO [#A] On the shortness of life :time:
+SCHEDULED: <2023-04-01 Sat>
+:PROPERTIES:
+:AUTHOR: Seneca
+:LOCATION: 1.1
+:ID: 1234-abcd
+:END:
+It is not that we have a short time to live,
+but that we waste a lot of it.
+** Letters
+* Epictetus
+Some things are in our control and others not.
+";
+        let options = ImportOptions::default();
+        assert_eq!(Some("org"), detected_format(text).as_deref());
+        let bullets = "* Buy milk\n* Call Sam\n\n## Later\nFix the bike\n";
+        assert_eq!(Some("markdown"), detected_format(bullets).as_deref());
+        let file = SourceFile {
+            path: "todo.org".into(),
+            bytes: bullets.as_bytes().to_vec(),
+        };
+        assert_eq!("org", detect_importer(&file).unwrap().name());
+        let cards = ImportFormat::Org
+            .parse_text(text.to_string(), &options)
+            .unwrap();
+        assert_eq!(2, cards.len());
+        let fc = &cards[0];
+        assert_eq!("On the shortness of life", fc.title);
+        assert_eq!(
+            "It is not that we have a short time to live,\nbut that we waste a lot of it.",
+            fc.body
+        );
+        assert_eq!("philosophy,latin,time", fc.tags);
+        assert_eq!("Seneca", fc.author);
+        assert_eq!("Stoics", fc.source_title);
+        assert_eq!("1.1", fc.location);
+        assert_eq!("1234-abcd", fc.metadata["id"]);
+        assert_eq!("philosophy", cards[1].tags);
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        export_org(&cards, dir.to_str().unwrap()).unwrap();
+        let exported = std::fs::read_to_string(dir.join("On the shortness of life.org")).unwrap();
+        assert!(dir.join("Epictetus.org").exists());
+        let again = ImportFormat::Org.parse_text(exported, &options).unwrap();
+        assert_eq!(1, again.len());
+        assert_eq!(fc.title, again[0].title);
+        assert_eq!(fc.body, again[0].body);
+        assert_eq!(fc.tags, again[0].tags);
+        assert_eq!(fc.author, again[0].author);
+        assert_eq!(fc.location, again[0].location);
+        assert_eq!(fc.metadata, again[0].metadata);
+    }
+
+    #[test]
+    pub fn test_epub_passages() {
+        use crate::import::{parse_epub, ImportOptions};
+
+        let epub = include_bytes!("../resources/fixtures/aphorisms.epub");
+        let options = ImportOptions::default();
+        assert!(parse_epub(epub, &options).is_err());
+
+        //numbered paragraphs
+        let options = ImportOptions {
+            passage_pattern: Some(regex::Regex::new(r"^\d+\.").unwrap()),
+            ..Default::default()
+        };
+        let cards = parse_epub(epub, &options).unwrap();
+        assert_eq!(3, cards.len());
+        assert_eq!("Maxims & Reflections", cards[0].title);
+        assert_eq!("François de La Rochefoucauld", cards[0].author);
+        assert_eq!(
+            "1. What we take for virtue is often nothing but an assemblage of different actions and interests.",
+            cards[0].body
+        );
+        assert_eq!("Moral Reflections", cards[0].location);
+        assert_eq!(
+            "19. We all have strength enough\nto endure the misfortunes of others.",
+            cards[2].body
+        );
+
+        let options = ImportOptions {
+            passage_markup: Some("p.maxim".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(2, parse_epub(epub, &options).unwrap().len());
+        let options = ImportOptions {
+            passage_markup: Some("blockquote".parse().unwrap()),
+            ..Default::default()
+        };
+        let cards = parse_epub(epub, &options).unwrap();
+        assert_eq!(1, cards.len());
+        assert!(cards[0].body.starts_with("19."));
+    }
+
+    #[test]
+    pub fn test_epub_import_remembers_its_options() {
+        use crate::db::{fetch_initial_flash_card_count, init_table};
+        use crate::import::{import_source_file, FileOutcome, ImportOptions};
+        use rusqlite::Connection;
+
+        let dir = tempfile::tempdir().unwrap();
+        let fp = dir.path().join("aphorisms.epub");
+        std::fs::write(&fp, include_bytes!("../resources/fixtures/aphorisms.epub")).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        let blockquotes = ImportOptions {
+            passage_markup: Some("blockquote".parse().unwrap()),
+            ..Default::default()
+        };
+        let maxims = ImportOptions {
+            passage_markup: Some("p.maxim".parse().unwrap()),
+            ..Default::default()
+        };
+        let first = import_source_file(&fp, &blockquotes, &conn).unwrap();
+        let again = import_source_file(&fp, &blockquotes, &conn).unwrap();
+        //a directory import without --markup doesn't trip over it
+        let unasked = import_source_file(&fp, &ImportOptions::default(), &conn).unwrap();
+        let other = import_source_file(&fp, &maxims, &conn).unwrap();
+
+        assert!(matches!(first, FileOutcome::Imported(_)));
+        assert!(matches!(again, FileOutcome::AlreadyImported));
+        assert!(matches!(unasked, FileOutcome::AlreadyImported));
+        let FileOutcome::Imported(summary) = other else {
+            panic!("epub was not imported again with other markup");
+        };
+        assert_eq!(2, summary.imported);
+        assert_eq!(3, fetch_initial_flash_card_count(false, &conn).unwrap());
+    }
+
+    #[test]
+    pub fn test_importer_registry() {
+        use crate::import::{importers, SourceFile, YomuImporter};
+        use std::collections::HashSet;
+
+        let names = importers()
+            .iter()
+            .map(|importer| importer.name().to_string())
+            .collect::<HashSet<_>>();
+        assert_eq!(importers().len(), names.len());
+
+        assert_eq!(
+            Some("yomu"),
+            detected_format("# Meditations (Marcus Aurelius)\n\n> a quote\n").as_deref()
+        );
+        //an ordinary note with a heading and a quote in it
+        let text = "# Untitled notes\n\n> a quote\n\n---\n";
+        assert_eq!(Some("markdown"), detected_format(text).as_deref());
+        let file = SourceFile {
+            path: "notes.md".into(),
+            bytes: text.as_bytes().to_vec(),
+        };
+        let parsed = YomuImporter.parse(&file, &Default::default()).unwrap();
+        assert_eq!("Untitled notes", parsed.cards[0].title);
+        assert_eq!(1, parsed.warnings.len());
+
+        assert_eq!(
+            Some("readera"),
+            detected_format("Book\nAuthor\nfirst\n*****\nsecond\n").as_deref()
+        );
+        assert_eq!(None, detected_format("Some prose with ***** stars in it\n"));
+        assert_eq!(None, detected_format("just some words"));
     }
 }
@@ -0,0 +1,291 @@
+use anyhow::{bail, Result};
+use serde_json::{Map, Number, Value};
+
+///Just enough Lua to read the tables KOReader keeps its highlights in:
+///`return { ["key"] = value, [1] = value, key = value, value }` with strings,
+///numbers, booleans and nested tables. Tables become JSON objects keyed by
+///whatever the Lua key was, so `[1]` turns into `"1"`, see array_values
+pub fn parse_lua_table(source: &str) -> Result<Value> {
+    let mut parser = LuaParser {
+        chars: source.chars().collect(),
+        pos: 0,
+    };
+    parser.skip_whitespace();
+    if parser.starts_with("return") {
+        parser.pos += "return".len();
+    }
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        bail!(
+            "unexpected text after the table at character {}",
+            parser.pos
+        );
+    }
+    Ok(value)
+}
+
+///The values of a table used as a list, in index order rather than the
+///alphabetical order the keys would otherwise come back in
+pub fn array_values(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(values) => values.iter().collect(),
+        Value::Object(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by_key(|(key, _)| key.parse::<usize>().unwrap_or(usize::MAX));
+            entries.into_iter().map(|(_, value)| value).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+struct LuaParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl LuaParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            bail!("expected '{}' at character {}", c, self.pos);
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    ///Whitespace and comments, both `-- line` and `--[[ block ]]`
+    fn skip_whitespace(&mut self) {
+        loop {
+            while self.peek().is_some_and(char::is_whitespace) {
+                self.pos += 1;
+            }
+            if self.starts_with("--[[") {
+                while self.pos < self.chars.len() && !self.starts_with("]]") {
+                    self.pos += 1;
+                }
+                self.pos += 2;
+            } else if self.starts_with("--") {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.parse_table(),
+            Some('"' | '\'') => Ok(Value::String(self.parse_string()?)),
+            Some('[') if self.starts_with("[[") => Ok(Value::String(self.parse_long_string()?)),
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '.' => self.parse_number(),
+            Some(c) if c.is_alphabetic() || c == '_' => match self.parse_name().as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                "nil" => Ok(Value::Null),
+                name => bail!("can't read '{}' at character {}", name, self.pos),
+            },
+            Some(c) => bail!("unexpected '{}' at character {}", c, self.pos),
+            None => bail!("the table ends too soon"),
+        }
+    }
+
+    fn parse_table(&mut self) -> Result<Value> {
+        self.expect('{')?;
+        let mut map = Map::new();
+        let mut next_index = 1;
+        loop {
+            self.skip_whitespace();
+            let key = match self.peek() {
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(map));
+                }
+                Some('[') if !self.starts_with("[[") => {
+                    self.pos += 1;
+                    let key = self.parse_value()?;
+                    self.expect(']')?;
+                    self.expect('=')?;
+                    key_string(&key)
+                }
+                Some(c) if c.is_alphabetic() || c == '_' => {
+                    let start = self.pos;
+                    let name = self.parse_name();
+                    self.skip_whitespace();
+                    if self.peek() == Some('=') {
+                        self.pos += 1;
+                        name
+                    } else {
+                        //a bare true, false or nil in a list
+                        self.pos = start;
+                        next_index += 1;
+                        (next_index - 1).to_string()
+                    }
+                }
+                _ => {
+                    next_index += 1;
+                    (next_index - 1).to_string()
+                }
+            };
+            let value = self.parse_value()?;
+            map.insert(key, value);
+            self.skip_whitespace();
+            if matches!(self.peek(), Some(',' | ';')) {
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn parse_number(&mut self) -> Result<Value> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.'))
+        {
+            self.pos += 1;
+        }
+        let text = self.chars[start..self.pos].iter().collect::<String>();
+        if let Ok(int) = text.parse::<i64>() {
+            return Ok(Value::Number(int.into()));
+        }
+        match text.parse::<f64>().ok().and_then(Number::from_f64) {
+            Some(number) => Ok(Value::Number(number)),
+            None => bail!("'{}' is not a number", text),
+        }
+    }
+
+    ///Quoted strings, with the escapes `string.format("%q")` writes.
+    ///Decimal escapes are bytes, so the text is put back together as UTF-8
+    fn parse_string(&mut self) -> Result<String> {
+        let quote = self.chars[self.pos];
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        let push = |bytes: &mut Vec<u8>, c: char| {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+        };
+        loop {
+            let Some(c) = self.peek() else {
+                bail!("string is never closed");
+            };
+            self.pos += 1;
+            if c == quote {
+                return Ok(String::from_utf8_lossy(&bytes).into_owned());
+            }
+            if c != '\\' {
+                push(&mut bytes, c);
+                continue;
+            }
+            let Some(escaped) = self.peek() else {
+                bail!("string is never closed");
+            };
+            self.pos += 1;
+            match escaped {
+                'n' | '\n' => bytes.push(b'\n'),
+                't' => bytes.push(b'\t'),
+                'r' => bytes.push(b'\r'),
+                'a' => bytes.push(7),
+                'b' => bytes.push(8),
+                'f' => bytes.push(12),
+                'v' => bytes.push(11),
+                '0'..='9' => {
+                    let mut code = escaped.to_digit(10).unwrap_or_default();
+                    for _ in 0..2 {
+                        match self.peek().and_then(|c| c.to_digit(10)) {
+                            Some(digit) => {
+                                code = code * 10 + digit;
+                                self.pos += 1;
+                            }
+                            None => break,
+                        }
+                    }
+                    match u8::try_from(code) {
+                        Ok(byte) => bytes.push(byte),
+                        Err(_) => bail!("escape \\{} is too big for a byte", code),
+                    }
+                }
+                other => push(&mut bytes, other),
+            }
+        }
+    }
+
+    fn parse_long_string(&mut self) -> Result<String> {
+        self.pos += 2;
+        let start = self.pos;
+        while !self.starts_with("]]") {
+            if self.pos >= self.chars.len() {
+                bail!("long string is never closed");
+            }
+            self.pos += 1;
+        }
+        let text = self.chars[start..self.pos].iter().collect::<String>();
+        self.pos += 2;
+        //like Lua, a newline straight after the opening brackets is dropped
+        Ok(text.strip_prefix('\n').unwrap_or(&text).to_string())
+    }
+}
+
+fn key_string(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    pub fn test_parse_lua_table() {
+        let source = r#"-- we can read Lua syntax here!
+return {
+    ["annotations"] = {
+        [2] = {
+            ["text"] = "second",
+        },
+        [1] = {
+            ["note"] = "line one\
+line two",
+            ["pageno"] = 12,
+            ["text"] = "say \"hi\"\226\128\148now",
+        },
+    },
+    ["percent_finished"] = 0.5,
+    finished = false,
+    ["list"] = { "a", 'b', },
+}
+"#;
+        let table = parse_lua_table(source).unwrap();
+        let annotations = array_values(&table["annotations"]);
+        assert_eq!(2, annotations.len());
+        assert_eq!("line one\nline two", annotations[0]["note"]);
+        assert_eq!(12, annotations[0]["pageno"]);
+        assert_eq!("say \"hi\"—now", annotations[0]["text"]);
+        assert_eq!("second", annotations[1]["text"]);
+        assert_eq!(0.5, table["percent_finished"]);
+        assert_eq!(false, table["finished"]);
+        assert_eq!(vec!["a", "b"], array_values(&table["list"]));
+        assert!(parse_lua_table("return { [1] = }").is_err());
+    }
+}
@@ -1,7 +1,8 @@
-use anyhow::{Context, Result};
-use app::{FlashCardMode, Select, State};
+use anyhow::{bail, Context, Result};
+use app::{FlashCardMode, ListPrompt, RandomStrategy, Select, State};
 use arboard::Clipboard;
-use clap::Parser;
+use bulk::{BulkAction, SelectionArgs};
+use clap::{Parser, Subcommand};
 use crossterm::{
     event::{self, Event, KeyCode},
     execute,
@@ -9,29 +10,32 @@ use crossterm::{
     ExecutableCommand,
 };
 use db::{default_connection, fetch_initial_flash_card_count};
-use import::import_yomu_quotes;
 use log::{info, LevelFilter};
 use ratatui::prelude::*;
+use report::ReportFormat;
 use rusqlite::Connection;
 use std::io::{stdout, Stdout};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 // use tracing::{info, instrument, Level};
 use log4rs::append::file::FileAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
 use tui_textarea::{Input, Key};
 
+use crate::app::App;
 use crate::db::init_table;
-use crate::{
-    app::{App, Timer},
-    db::construct_title_report,
-};
 
 mod app;
+mod bulk;
 mod db;
+mod export;
 mod import;
+mod lua;
+mod plugin;
 mod report;
+mod serve;
 mod ui;
+mod validate;
 
 ///Command line arguments for clap
 #[derive(Parser)]
@@ -45,21 +49,146 @@ pub struct Args {
     ///How much to spew to the file
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbosity: u8,
-    ///Is there a markdown file to read text from?
+    ///Is there a markdown file to read text from? Directories and globs work too
     #[arg(short, long)]
     file: Option<String>,
     ///Display default random flashcard every N seconds
     #[arg(short, long)]
     timer: Option<usize>,
     ///Set display mode for timer: Forward, Backward, Random
-    #[arg(short, long, requires("timer"), value_enum)]
+    #[arg(short, long, value_enum)]
     mode: Option<FlashCardMode>,
+    ///Only study starred cards
+    #[arg(long)]
+    favourites: bool,
+    ///How random cards are picked: Uniform, Weighted, Shuffle
+    #[arg(long, value_enum, default_value_t = RandomStrategy::Uniform)]
+    random: RandomStrategy,
+    ///Timer shows each card for as long as it takes to read, instead of a fixed time
+    #[arg(short = 'w', long)]
+    reading_time: bool,
+    ///Reading speed used by --reading-time
+    #[arg(long, default_value_t = 200)]
+    wpm: usize,
+    ///Fewest seconds a card is shown for with --reading-time
+    #[arg(long, default_value_t = 3)]
+    min_display: usize,
+    ///Most seconds a card is shown for with --reading-time
+    #[arg(long, default_value_t = 60)]
+    max_display: usize,
     ///if passed, will be "flip mode", showing title until space bar is pressed
     #[arg(short = 'l', long)]
     flip_mode: bool,
     ///Give info on number of
     #[arg(short, long)]
     report: bool,
+    ///How to print the report: Text, Json, Csv
+    #[arg(long, requires("report"), value_enum, default_value_t = ReportFormat::Text)]
+    format: ReportFormat,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+///Importing, plus bulk operations on the cards picked out by the selection,
+///where a summary is shown and confirmed before anything changes
+#[derive(Subcommand)]
+pub enum Command {
+    ///Import highlight files, directories of them, or globs like 'exports/*.md'
+    Import {
+        #[arg(required_unless_present_any = ["watch", "apple_books", "list_formats"])]
+        paths: Vec<String>,
+        ///Read every file as this format, one of --list-formats, instead of working it out
+        #[arg(long, value_name = "NAME")]
+        format: Option<String>,
+        ///List the formats we can import, including rashcard-import-<name> plugins
+        ///on the PATH or in ~/.config/rashcard/plugins, and stop
+        #[arg(long, exclusive = true)]
+        list_formats: bool,
+        ///Import files again even if they have been imported before unchanged
+        #[arg(long)]
+        force: bool,
+        ///Keep watching this directory, importing new and changed files as they turn up
+        #[arg(long, value_name = "DIR", conflicts_with = "force")]
+        watch: Option<String>,
+        ///Heading level that starts a new card in plain markdown notes
+        #[arg(long, default_value_t = import::DEFAULT_HEADING_LEVEL,
+              value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=6))]
+        heading_level: usize,
+        ///Take the epub paragraphs matching this regular expression
+        #[arg(long = "match", value_name = "REGEX")]
+        passage_pattern: Option<regex::Regex>,
+        ///Take the epub paragraphs inside this markup: a tag, a .class or both, eg p.maxim
+        #[arg(long = "markup", value_name = "SELECTOR")]
+        passage_markup: Option<import::MarkupSelector>,
+        ///Copies of Apple Books' AEAnnotation and BKLibrary sqlite databases
+        #[arg(long, num_args = 2, value_names = ["ANNOTATIONS", "LIBRARY"])]
+        apple_books: Option<Vec<String>>,
+        ///Show what importing the paths would do without saving anything
+        #[arg(long, conflicts_with_all = ["watch", "apple_books"])]
+        dry_run: bool,
+        ///Preview each file and pick which of its cards to import
+        #[arg(short, long, conflicts_with_all = ["dry_run", "watch", "apple_books"])]
+        interactive: bool,
+    },
+    ///Add a card from stdin, or the clipboard: the first line is the title, the rest the body
+    Add {
+        ///Take the card from the clipboard instead of stdin
+        #[arg(short, long)]
+        clipboard: bool,
+    },
+    ///Serve a JSON API for the cards on localhost, for scripts and browser extensions
+    Serve {
+        #[arg(short, long, default_value_t = serve::DEFAULT_PORT)]
+        port: u16,
+        ///Only answer requests sending `Authorization: Bearer <TOKEN>`
+        #[arg(long)]
+        token: Option<String>,
+    },
+    ///Delete the selected cards
+    Delete {
+        #[command(flatten)]
+        selection: SelectionArgs,
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    ///Replace the tags of the selected cards with a comma separated list
+    Retag {
+        tags: String,
+        #[command(flatten)]
+        selection: SelectionArgs,
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    ///Move the selected cards into a deck
+    Move {
+        deck: String,
+        #[command(flatten)]
+        selection: SelectionArgs,
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    ///Set the priority of the selected cards, higher comes up more with --random weighted
+    Priority {
+        priority: usize,
+        #[command(flatten)]
+        selection: SelectionArgs,
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    ///Write the selected cards out to a markdown file, a Readwise style csv file
+    ///if the name ends in .csv, or org files, one per title, if it is a directory
+    Export {
+        out: String,
+        #[command(flatten)]
+        selection: SelectionArgs,
+        ///Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
 }
 
 fn init_logging(level: u8) -> Result<()> {
@@ -84,8 +213,6 @@ fn init_logging(level: u8) -> Result<()> {
 }
 
 ///TODO Add open file dialog
-///TODO add plugin framework for formats
-///TODO convert readme reading into plugin
 ///TODO search through cards
 /// Rash: obsolete definition : quickly effective
 /// TODO keep list of which cards have been shown, to allow forward and backward navigation
@@ -98,45 +225,96 @@ fn main() -> Result<()> {
     let conn = default_connection().context("failed to get sql connection")?;
     init_table(&conn)?;
     if let Some(file) = args.file {
-        // import_read_era_quotes(&file, &conn)?;
-        import_yomu_quotes(&file, &conn)?;
-        println!("Imported flashcards from {}", file);
-        return Ok(());
+        return import::import_paths(&[file], &Default::default(), &conn);
+    }
+
+    if let Some(command) = args.command {
+        return run_command(command, &conn);
     }
 
     if args.report {
-        let title_report = construct_title_report(&conn)?;
-        report::print_out_report(&title_report)?;
+        let report = report::Report::construct(&conn)?;
+        report::print_out_report(&report, args.format)?;
         return Ok(());
     }
-    let mut maybe_timer = maybe_construct_timer(&args);
+    if app.timer.enabled {
+        info!(
+            "We have a timer! {}s, reading time {}",
+            app.timer.next_card_cycle, app.timer.reading_time
+        );
+    }
     let mut terminal = setup_terminal().context("setup failed")?;
-    run(app, &conn, &mut maybe_timer, &mut terminal).context("failed running")?;
+    run(app, &conn, &mut terminal).context("failed running")?;
     // tracing::debug!()
     // let mut terminal = Terminal::new(CrosstermBackend::new(stdout()));
     unsetup_terminal(&mut terminal).context("unsetup failed")
 }
 
-fn maybe_construct_timer(args: &Args) -> Option<Timer> {
-    if let Some(t) = args.timer {
-        let start = Instant::now();
-        let next_card_cycle = t;
-        let draw_mode = if let Some(mode) = args.mode.clone() {
-            mode
-        } else {
-            FlashCardMode::Random
-        };
-
-        let timer = Timer {
-            start,
-            next_card_cycle,
-            draw_mode,
-        };
-        info!("We have a timer! {}s", t);
-        Some(timer)
-    } else {
-        None
-    }
+fn run_command(command: Command, conn: &Connection) -> Result<()> {
+    let (action, selection, yes) = match command {
+        Command::Import {
+            paths,
+            list_formats,
+            format,
+            force,
+            watch,
+            heading_level,
+            passage_pattern,
+            passage_markup,
+            apple_books,
+            dry_run,
+            interactive,
+        } => {
+            if list_formats {
+                import::print_formats();
+                return Ok(());
+            }
+            let options = import::ImportOptions {
+                force,
+                heading_level,
+                passage_pattern,
+                passage_markup,
+                format,
+            };
+            if dry_run || interactive {
+                return import::preview_paths(&paths, &options, interactive, conn);
+            }
+            if !paths.is_empty() {
+                import::import_paths(&paths, &options, conn)?;
+            }
+            if let Some([annotations, library]) = apple_books.as_deref() {
+                import::import_apple_books(annotations, library, force, conn)?;
+            }
+            if let Some(dir) = watch {
+                import::watch_directory(&dir, &options, conn)?;
+            }
+            return Ok(());
+        }
+        Command::Add { clipboard } => return add_flashcard(clipboard, conn),
+        Command::Serve { port, token } => return serve::serve(port, token, conn),
+        Command::Delete { selection, yes } => (BulkAction::Delete, selection, yes),
+        Command::Retag {
+            tags,
+            selection,
+            yes,
+        } => (BulkAction::Retag(tags), selection, yes),
+        Command::Move {
+            deck,
+            selection,
+            yes,
+        } => (BulkAction::Move(deck), selection, yes),
+        Command::Priority {
+            priority,
+            selection,
+            yes,
+        } => (BulkAction::Prioritise(priority), selection, yes),
+        Command::Export {
+            out,
+            selection,
+            yes,
+        } => (BulkAction::Export(out), selection, yes),
+    };
+    bulk::run_bulk_action(&action, &selection.into(), yes, conn)
 }
 
 fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
@@ -160,12 +338,13 @@ fn unsetup_terminal(term: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()>
 fn run(
     mut app: App,
     conn: &Connection,
-    maybe_timer: &mut Option<Timer>,
     term: &mut Terminal<CrosstermBackend<Stdout>>,
 ) -> Result<()> {
     //create the table if need be
-    let flash_card_count = fetch_initial_flash_card_count(conn)?;
+    let flash_card_count = fetch_initial_flash_card_count(app.favourites_only, conn)?;
     app.total_cards = flash_card_count;
+    //bumped whenever another process, like `import --watch`, commits to the database
+    let mut data_version = db::data_version(conn)?;
 
     loop {
         term.draw(|f| ui::render_app(f, &mut app))?;
@@ -173,16 +352,20 @@ fn run(
         if !app.running {
             break;
         }
-        //we want to flick through if we've been passed a timer
-        if let Some(t) = maybe_timer {
-            if t.start.elapsed().as_secs() > t.next_card_cycle as u64 {
-                match t.draw_mode {
-                    FlashCardMode::Forward => show_next_flashcard(&mut app, conn)?,
-                    FlashCardMode::Backward => show_prev_flashcard(&mut app, conn)?,
-                    FlashCardMode::Random => show_random_flashcard(&mut app, conn)?,
-                }
-                t.start = Instant::now();
+        let latest_version = db::data_version(conn)?;
+        if latest_version != data_version {
+            data_version = latest_version;
+            app.total_cards = fetch_initial_flash_card_count(app.favourites_only, conn)?;
+        }
+        //we want to flick through if the timer is on
+        app.timer.fit_to_text(&app.current_flash_text);
+        if app.timer_can_advance() {
+            match app.draw_mode {
+                FlashCardMode::Forward => show_next_flashcard(&mut app, conn)?,
+                FlashCardMode::Backward => show_prev_flashcard(&mut app, conn)?,
+                FlashCardMode::Random => show_random_flashcard(&mut app, conn)?,
             }
+            app.timer.restart();
         }
     }
     Ok(())
@@ -227,11 +410,18 @@ fn read_input(app: &mut App, conn: &Connection) -> Result<()> {
                     match key.code {
                         KeyCode::Char('q') | KeyCode::Char('Q') => app.stop_running(),
                         KeyCode::Char('a') | KeyCode::Char('A') => app.show_add_flashcard(),
-                        KeyCode::Char('n') | KeyCode::Char('N') => show_next_flashcard(app, conn)?,
+                        KeyCode::Char('n') | KeyCode::Char('N') => {
+                            show_next_flashcard(app, conn)?;
+                            review_current_flashcard(app, conn)?
+                        }
                         KeyCode::Char('r') | KeyCode::Char('R') => {
-                            show_random_flashcard(app, conn)?
+                            show_random_flashcard(app, conn)?;
+                            review_current_flashcard(app, conn)?
+                        }
+                        KeyCode::Char('p') | KeyCode::Char('P') => {
+                            show_prev_flashcard(app, conn)?;
+                            review_current_flashcard(app, conn)?
                         }
-                        KeyCode::Char('p') | KeyCode::Char('P') => show_prev_flashcard(app, conn)?,
                         KeyCode::Char('f') | KeyCode::Char('F') => app.flip_flashcard(),
                         KeyCode::Char('b') | KeyCode::Char('B') => app.idle(),
                         KeyCode::Char('j')
@@ -241,13 +431,34 @@ fn read_input(app: &mut App, conn: &Connection) -> Result<()> {
                         KeyCode::Char('k') | KeyCode::Char('K') | KeyCode::Up | KeyCode::PageUp => {
                             app.scroll_up()
                         }
-                        KeyCode::Char('d') | KeyCode::Char('D') => {
-                            maybe_delete_flashcard(app, conn)?
-                        }
+                        KeyCode::Char('d') | KeyCode::Char('D') => maybe_delete_flashcard(app)?,
+                        KeyCode::Char('l') | KeyCode::Char('L') => show_card_list(app, conn)?,
                         KeyCode::Char('y') | KeyCode::Char('Y') => {
                             copy_flashcard_to_clipboard(app)?
                         }
+                        KeyCode::Char('e') | KeyCode::Char('E') => {
+                            new_flashcard_from_clipboard(app)?
+                        }
                         KeyCode::Char(' ') => flip_flash_card(app)?,
+                        KeyCode::Char('t') | KeyCode::Char('T') => app.timer.toggle_enabled(),
+                        KeyCode::Char('s') | KeyCode::Char('S') => app.timer.toggle_pause(),
+                        KeyCode::Char('+') | KeyCode::Char('=') => app.timer.lengthen(),
+                        KeyCode::Char('-') | KeyCode::Char('_') => app.timer.shorten(),
+                        KeyCode::Char('m') | KeyCode::Char('M') => app.cycle_draw_mode(),
+                        KeyCode::Char('w') | KeyCode::Char('W') => app.timer.toggle_reading_time(),
+                        KeyCode::Char('*') => toggle_favourite(app, conn)?,
+                        KeyCode::Char('g') | KeyCode::Char('G') => {
+                            app.show_stats(db::construct_study_stats(conn)?)
+                        }
+                        KeyCode::Char('c') | KeyCode::Char('C') => {
+                            grade_flashcard(app, conn, true)?
+                        }
+                        KeyCode::Char('x') | KeyCode::Char('X') => {
+                            grade_flashcard(app, conn, false)?
+                        }
+                        KeyCode::Char('v') | KeyCode::Char('V') => {
+                            toggle_favourites_only(app, conn)?
+                        }
                         _ => info!("Go baby go go!"),
                     }
                 }
@@ -267,6 +478,66 @@ fn read_input(app: &mut App, conn: &Connection) -> Result<()> {
                     }
                 }
             }
+            State::ShowStats => {
+                if let Event::Key(key) = event::read().context("event read failed")? {
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Char('Q') => app.stop_running(),
+                        KeyCode::Char('b') | KeyCode::Char('B') | KeyCode::Esc => {
+                            app.restore_prior_state()
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            State::ListFlashcards => {
+                if let Event::Key(key) = event::read().context("event read failed")? {
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Char('Q') => app.stop_running(),
+                        KeyCode::Char('b') | KeyCode::Char('B') | KeyCode::Esc => app.idle(),
+                        KeyCode::Char('j') | KeyCode::Char('J') | KeyCode::Down => app.list_down(),
+                        KeyCode::Char('k') | KeyCode::Char('K') | KeyCode::Up => app.list_up(),
+                        KeyCode::Char(' ') => app.toggle_highlighted_selection(),
+                        KeyCode::Char('*') => app.toggle_select_all(),
+                        KeyCode::Char('t') | KeyCode::Char('T') => app.select_same_title(),
+                        KeyCode::Char('/') => app.start_list_prompt(ListPrompt::Search),
+                        KeyCode::Char('d') | KeyCode::Char('D') => app.start_bulk_delete(),
+                        KeyCode::Char('g') | KeyCode::Char('G') => {
+                            app.start_list_prompt(ListPrompt::Retag)
+                        }
+                        KeyCode::Char('m') | KeyCode::Char('M') => {
+                            app.start_list_prompt(ListPrompt::Move)
+                        }
+                        KeyCode::Char('x') | KeyCode::Char('X') => {
+                            app.start_list_prompt(ListPrompt::Export)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            State::ListPrompt => {
+                if let Event::Key(key) = event::read().context("event read failed")? {
+                    match key.code {
+                        KeyCode::Esc => app.restore_prior_state(),
+                        KeyCode::Enter => submit_list_prompt(app, conn)?,
+                        KeyCode::Backspace => {
+                            app.prompt_input.pop();
+                        }
+                        KeyCode::Char(c) => app.prompt_input.push(c),
+                        _ => {}
+                    }
+                }
+            }
+            State::DisplayBulkDeletePopup => {
+                if let Event::Key(key) = event::read().context("event read failed")? {
+                    match key.code {
+                        KeyCode::Char('y') | KeyCode::Char('Y') => {
+                            apply_bulk_action_to_selection(app, conn, BulkAction::Delete)?
+                        }
+                        KeyCode::Char('n') | KeyCode::Char('N') => app.restore_prior_state(),
+                        _ => {}
+                    }
+                }
+            }
             //all the other states don't need no stinking input
             _ => {}
         }
@@ -291,6 +562,42 @@ fn copy_flashcard_to_clipboard(app: &mut App) -> Result<()> {
     Ok(())
 }
 
+///Start a new card with whatever is on the clipboard, to be tidied up and saved as usual
+fn new_flashcard_from_clipboard(app: &mut App) -> Result<()> {
+    match Clipboard::new().and_then(|mut clip| clip.get_text()) {
+        Ok(text) if !text.trim().is_empty() => app.show_add_flashcard_with(&text),
+        Ok(_) => app.display_message_popup("Nothing on the clipboard to paste"),
+        Err(e) => app.display_message_popup(&format!("Couldn't read the clipboard: {}", e)),
+    }
+    Ok(())
+}
+
+///`rashcard add`, checked the same way the editor checks cards
+fn add_flashcard(from_clipboard: bool, conn: &Connection) -> Result<()> {
+    let text = if from_clipboard {
+        Clipboard::new()
+            .and_then(|mut clip| clip.get_text())
+            .context("couldn't read the clipboard")?
+    } else {
+        std::io::read_to_string(std::io::stdin())?
+    };
+    let checked = validate::check_flashcard(db::FlashCard::from_text(&text));
+    if !checked.is_valid() {
+        bail!("not saved: {}", checked.describe_problems());
+    }
+    db::save_flashcard_object(&checked.card, conn)?;
+    if checked.problems.is_empty() {
+        println!("Saved {}", checked.card.title);
+    } else {
+        println!(
+            "Saved {}, but {}",
+            checked.card.title,
+            checked.describe_problems()
+        );
+    }
+    Ok(())
+}
+
 fn save_flashcard(app: &mut App, conn: &Connection) -> Result<()> {
     // println!("About to save the flash card");
     //get the text from app
@@ -299,17 +606,21 @@ fn save_flashcard(app: &mut App, conn: &Connection) -> Result<()> {
     if lines.is_empty() {
         return Ok(());
     }
-    //we have at least one line
-    //top line is title
-    let title = lines.first().unwrap();
-    //
-    //everything else is body
-    let body = &lines[1..].join("\n");
-
-    db::save_flashcard(title, body, conn)?;
+    //top line is title, everything else is body
+    let checked = validate::check_flashcard(db::FlashCard::from_text(&lines.join("\n")));
+    if !checked.is_valid() {
+        info!("Not saving flashcard: {}", checked.describe_problems());
+        app.display_message_popup(&format!("Not saved: {}", checked.describe_problems()));
+        return Ok(());
+    }
+    db::save_flashcard_object(&checked.card, conn)?;
 
-    app.display_saved_popup();
-    app.total_cards += 1;
+    if checked.problems.is_empty() {
+        app.display_saved_popup();
+    } else {
+        app.display_message_popup(&format!("Saved, but {}", checked.describe_problems()));
+    }
+    app.total_cards = fetch_initial_flash_card_count(app.favourites_only, conn)?;
     Ok(())
 }
 
@@ -339,20 +650,37 @@ fn show_flashcard(app: &mut App, conn: &Connection, state: Select) -> Result<()>
         match state {
             Select::Next => app.increment_flash_count(),
             Select::Prev => app.decrement_flash_count(),
-            Select::Random => app.randomize_flash_count(),
+            Select::Random => match app.random_strategy {
+                RandomStrategy::Uniform => app.randomize_flash_count(),
+                RandomStrategy::Weighted => {
+                    let weights = db::fetch_card_weights(app.favourites_only, conn)?
+                        .iter()
+                        .map(db::CardWeight::weight)
+                        .collect::<Vec<_>>();
+                    app.pick_weighted_flash_count(&weights)
+                }
+                RandomStrategy::Shuffle => app.draw_from_shuffle_bag(),
+            },
         }
     };
 
     let offset = app.current_flashcard_number;
     info!("Current flash number: {}", offset);
     info!("State: {:?}", state);
-    if let Some(flash) = db::next_flashcard(offset, conn)? {
+    if let Some(flash) = db::next_flashcard(offset, app.favourites_only, conn)? {
         app.current_flash_title = flash.title.clone();
         app.current_flash_body = flash.body.clone();
         app.current_flash_text = format!("{}\n{}", app.current_flash_title, app.current_flash_body);
         app.current_flashcard_id = flash.id;
+        app.current_flash_favourite = flash.favourite;
+        app.current_flash_source = flash.source_line();
+        app.current_flash_reviewed = false;
+        db::record_view(flash.id, conn)?;
     } else {
         app.reset_count();
+        app.current_flash_favourite = false;
+        app.current_flash_reviewed = true;
+        app.current_flash_source.clear();
         app.current_flash_title = "No flashcards".to_string();
     };
 
@@ -367,8 +695,57 @@ fn show_flashcard(app: &mut App, conn: &Connection, state: Select) -> Result<()>
     Ok(())
 }
 
+///Log the card on display as studied, once however many times it is graded
+fn review_current_flashcard(app: &mut App, conn: &Connection) -> Result<()> {
+    if app.has_flashcards() && !app.current_flash_reviewed {
+        db::record_review(app.current_flashcard_id, conn)?;
+        app.current_flash_reviewed = true;
+    }
+    Ok(())
+}
+
+///Star or unstar the card on display
+fn toggle_favourite(app: &mut App, conn: &Connection) -> Result<()> {
+    if app.state == State::ShowFlashcard && app.has_flashcards() {
+        app.current_flash_favourite = db::toggle_favourite(app.current_flashcard_id, conn)?;
+        info!(
+            "Flashcard {} favourite: {}",
+            app.current_flashcard_id, app.current_flash_favourite
+        );
+        if app.favourites_only {
+            //unstarring takes it out of what we are studying
+            app.total_cards = fetch_initial_flash_card_count(true, conn)?;
+        }
+    }
+    Ok(())
+}
+
+///Remembered it or not, for the accuracy on the stats screen
+fn grade_flashcard(app: &mut App, conn: &Connection, correct: bool) -> Result<()> {
+    if app.state == State::ShowFlashcard && app.has_flashcards() {
+        //the timer may have put the card up, grading it means it was studied
+        review_current_flashcard(app, conn)?;
+        db::grade_latest_review(app.current_flashcard_id, correct, conn)?;
+        info!(
+            "Graded flashcard {} correct: {}",
+            app.current_flashcard_id, correct
+        );
+    }
+    Ok(())
+}
+
+///Switch between studying all cards and only the starred ones,
+///offsets mean different cards now so start again from the top
+fn toggle_favourites_only(app: &mut App, conn: &Connection) -> Result<()> {
+    app.favourites_only = !app.favourites_only;
+    app.total_cards = fetch_initial_flash_card_count(app.favourites_only, conn)?;
+    app.forget_history();
+    app.shuffle_bag.clear();
+    show_next_flashcard(app, conn)
+}
+
 ///This will cause a "Really delete" modal to display
-fn maybe_delete_flashcard(app: &mut App, conn: &Connection) -> Result<()> {
+fn maybe_delete_flashcard(app: &mut App) -> Result<()> {
     info!("Maybe deleting a flashcard!");
     if app.has_flashcards() {
         //show the confirm delete dialog
@@ -391,3 +768,54 @@ fn actually_delete_flashcard(app: &mut App, conn: &Connection) -> Result<()> {
 
     Ok(())
 }
+
+///Load the cards matching the current list query and show them
+fn show_card_list(app: &mut App, conn: &Connection) -> Result<()> {
+    refresh_card_list(app, conn)?;
+    app.show_card_list();
+    Ok(())
+}
+
+fn refresh_card_list(app: &mut App, conn: &Connection) -> Result<()> {
+    let query = app.list_query.trim();
+    let selection = db::CardSelection {
+        query: (!query.is_empty()).then(|| query.to_string()),
+        ..Default::default()
+    };
+    app.load_card_list(db::find_flashcards(&selection, conn)?);
+    Ok(())
+}
+
+fn submit_list_prompt(app: &mut App, conn: &Connection) -> Result<()> {
+    match app.prompted_bulk_action() {
+        None => {
+            app.list_query = app.prompt_input.trim().to_string();
+            refresh_card_list(app, conn)?;
+            app.restore_prior_state();
+        }
+        //need somewhere to write to
+        Some(BulkAction::Export(fp)) if fp.is_empty() => app.restore_prior_state(),
+        Some(action) => apply_bulk_action_to_selection(app, conn, action)?,
+    }
+    Ok(())
+}
+
+fn apply_bulk_action_to_selection(
+    app: &mut App,
+    conn: &Connection,
+    action: BulkAction,
+) -> Result<()> {
+    let cards = app.selected_flashcards();
+    if !cards.is_empty() {
+        let changed = bulk::apply_bulk_action(&action, &cards, conn)?;
+        info!("Bulk {:?} on {} flashcards", action, changed);
+        app.list_message = format!("{}: {} flashcards", action.describe(), changed);
+        if action == BulkAction::Delete {
+            app.total_cards = fetch_initial_flash_card_count(app.favourites_only, conn)?;
+            app.forget_history();
+        }
+        refresh_card_list(app, conn)?;
+    }
+    app.show_card_list();
+    Ok(())
+}
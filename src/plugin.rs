@@ -0,0 +1,294 @@
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+
+use crate::db::FlashCard;
+use crate::import::{ImportOptions, Importer, ParsedImport, SourceFile};
+
+///Executables called this followed by a format name are importer plugins
+pub const PLUGIN_PREFIX: &str = "rashcard-import-";
+
+///How long a plugin gets to read a file before it is killed
+pub const PLUGIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+///An importer that lives outside the binary. It is run with the file's path as its
+///only argument and prints one JSON card per line, using the same field names as
+///FlashCard, eg `{"title": "Meditations", "body": "...", "metadata": {"page": "4"}}`.
+///Whatever it writes to stderr is passed on as warnings, a failing exit stops the import,
+///and so does taking longer than the timeout
+#[derive(Debug, Clone)]
+pub struct ExternalImporter {
+    pub name: String,
+    pub path: PathBuf,
+    description: String,
+    timeout: Duration,
+}
+
+impl Importer for ExternalImporter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    ///Plugins get files with their name as the extension, anything else has to be
+    ///handed to them with --format
+    fn sniff(&self, file: &SourceFile) -> bool {
+        file.has_extension(&self.name)
+    }
+
+    fn parse(&self, file: &SourceFile, _options: &ImportOptions) -> Result<ParsedImport> {
+        let (status, stdout, stderr) = self.run(&file.path)?;
+        if !status.success() {
+            bail!(
+                "plugin {} failed with {}: {}",
+                self.name,
+                status,
+                stderr.trim()
+            );
+        }
+        let saved_fields = match serde_json::to_value(FlashCard::default())? {
+            Value::Object(fields) => fields.into_iter().map(|(key, _)| key).collect(),
+            _ => HashSet::new(),
+        };
+        let mut parsed = ParsedImport::default();
+        for (i, line) in stdout.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let card = serde_json::from_str::<Value>(line).and_then(|value| {
+                if let Value::Object(fields) = &value {
+                    //ids are handed out by the database
+                    let ignored = fields
+                        .keys()
+                        .filter(|key| *key == "id" || !saved_fields.contains(*key))
+                        .map(String::as_str)
+                        .collect::<Vec<_>>();
+                    if !ignored.is_empty() {
+                        parsed.warnings.push(format!(
+                            "line {} has fields that aren't saved, {}",
+                            i + 1,
+                            ignored.join(", ")
+                        ));
+                    }
+                }
+                serde_json::from_value::<FlashCard>(value)
+            });
+            match card {
+                Ok(fc) => parsed.cards.push(FlashCard { id: 0, ..fc }),
+                Err(e) => parsed
+                    .warnings
+                    .push(format!("line {} is not a card, {}", i + 1, e)),
+            }
+        }
+        parsed.warnings.extend(
+            stderr
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| format!("{} says {}", self.name, line.trim())),
+        );
+        Ok(parsed)
+    }
+}
+
+impl ExternalImporter {
+    ///Run the plugin on a file, killing it if it outlasts the timeout.
+    ///Its output is read as it comes so a chatty plugin can't fill the pipe and stall
+    fn run(&self, file: &Path) -> Result<(std::process::ExitStatus, String, String)> {
+        let mut child = Command::new(&self.path)
+            .arg(file)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let read_all = |mut pipe: Box<dyn Read + Send>| {
+            std::thread::spawn(move || {
+                let mut bytes = Vec::new();
+                pipe.read_to_end(&mut bytes).map(|_| bytes)
+            })
+        };
+        let stdout = read_all(Box::new(child.stdout.take().ok_or(anyhow!("no stdout"))?));
+        let stderr = read_all(Box::new(child.stderr.take().ok_or(anyhow!("no stderr"))?));
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() > self.timeout {
+                child.kill()?;
+                child.wait()?;
+                bail!(
+                    "plugin {} took longer than {}s and was stopped",
+                    self.name,
+                    self.timeout.as_secs_f64()
+                );
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        let text = |reader: std::thread::JoinHandle<std::io::Result<Vec<u8>>>| -> Result<String> {
+            let bytes = reader
+                .join()
+                .map_err(|_| anyhow!("couldn't read plugin {}", self.name))??;
+            Ok(String::from_utf8_lossy(&bytes).into_owned())
+        };
+        Ok((status, text(stdout)?, text(stderr)?))
+    }
+}
+
+///Where plugins go if they aren't on the PATH, `~/.config/rashcard/plugins`
+pub fn plugin_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("rashcard").join("plugins"))
+}
+
+///Plugins in the config directory and on the PATH, looked for once per run.
+///The config directory wins if a name turns up twice
+pub fn plugins() -> &'static [ExternalImporter] {
+    static PLUGINS: OnceLock<Vec<ExternalImporter>> = OnceLock::new();
+    PLUGINS.get_or_init(|| {
+        let mut dirs = plugin_dir().into_iter().collect::<Vec<_>>();
+        if let Some(path) = std::env::var_os("PATH") {
+            dirs.extend(std::env::split_paths(&path));
+        }
+        discover_plugins(&dirs)
+    })
+}
+
+///Every `rashcard-import-<name>` executable in the directories, first one found for each name
+pub fn discover_plugins(dirs: &[PathBuf]) -> Vec<ExternalImporter> {
+    let mut seen = HashSet::new();
+    let mut plugins = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut found = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| is_executable(path))
+            .filter_map(|path| {
+                let file_name = path.file_name()?.to_str()?;
+                let name = file_name
+                    .strip_prefix(PLUGIN_PREFIX)?
+                    .trim_end_matches(".exe")
+                    .to_string();
+                Some((name, path))
+            })
+            .filter(|(name, _)| !name.is_empty())
+            .collect::<Vec<_>>();
+        found.sort();
+        for (name, path) in found {
+            if seen.insert(name.clone()) {
+                plugins.push(ExternalImporter {
+                    description: format!("plugin, {}", path.display()),
+                    name,
+                    path,
+                    timeout: PLUGIN_TIMEOUT,
+                });
+            }
+        }
+    }
+    plugins
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    pub fn test_external_importer() {
+        use crate::db::{fetch_initial_flash_card_count, init_table};
+        use crate::import::{number_cards, save_numbered_flashcards};
+        use rusqlite::Connection;
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let script = dir.join("rashcard-import-quotes");
+        std::fs::write(
+            &script,
+            r#"#!/bin/sh
+echo '{"id": 9, "title": "Meditations", "body": "Waste no more time", "favourite": true, "colour": "red", "metadata": {"book": "10"}}'
+echo 'not json'
+echo '{"title": "Meditations", "body": ""}'
+echo "read $1" >&2
+"#,
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.join("rashcard-import-notrun"), "not executable").unwrap();
+
+        let plugins = discover_plugins(std::slice::from_ref(&dir));
+        assert_eq!(1, plugins.len());
+        assert_eq!("quotes", plugins[0].name);
+        let file = SourceFile {
+            path: dir.join("highlights.quotes"),
+            bytes: Vec::new(),
+        };
+        assert!(plugins[0].sniff(&file));
+        let parsed = plugins[0].parse(&file, &Default::default()).unwrap();
+
+        assert_eq!(2, parsed.cards.len());
+        assert_eq!("10", parsed.cards[0].metadata["book"]);
+        assert!(parsed.cards[0].favourite);
+        assert_eq!(3, parsed.warnings.len());
+        assert!(parsed.warnings[0].ends_with("colour, id"));
+        assert!(parsed.warnings[2].ends_with("highlights.quotes"));
+
+        let conn = Connection::open_in_memory().unwrap();
+        init_table(&conn).unwrap();
+        let summary = save_numbered_flashcards(number_cards(parsed.cards), &conn).unwrap();
+        assert_eq!(1, summary.imported);
+        assert_eq!(1, summary.skipped.len());
+        assert_eq!(1, fetch_initial_flash_card_count(false, &conn).unwrap());
+        assert_eq!(1, fetch_initial_flash_card_count(true, &conn).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn test_slow_plugin_is_stopped() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let script = dir.join("rashcard-import-slow");
+        std::fs::write(&script, "#!/bin/sh\nexec sleep 10\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let plugin = ExternalImporter {
+            timeout: Duration::from_millis(200),
+            ..discover_plugins(std::slice::from_ref(&dir)).remove(0)
+        };
+        let file = SourceFile {
+            path: dir.join("notes.slow"),
+            bytes: Vec::new(),
+        };
+        let start = Instant::now();
+        let result = plugin.parse(&file, &Default::default());
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,17 +1,250 @@
+use std::io::{stdout, Write};
+
 use anyhow::Result;
 use colored::Colorize;
+use rusqlite::Connection;
+use serde::Serialize;
 
 use crate::db;
 
-pub fn print_out_report(title_report: &db::CardTitleReport) -> Result<()> {
+///How many cards to list as most and least viewed
+const VIEW_EXTREMES: usize = 5;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+///Everything we know about the collection, in one place so it can be printed any which way
+#[derive(Serialize)]
+pub struct Report {
+    pub total_cards: usize,
+    pub titles: Vec<db::ReportLine>,
+    pub authors: Vec<db::CountLine>,
+    pub added_per_month: Vec<db::CountLine>,
+    pub most_viewed: Vec<db::CardViews>,
+    pub least_viewed: Vec<db::CardViews>,
+    pub average_body_length: f64,
+    pub problem_cards: Vec<db::ProblemCard>,
+    pub favourites: Vec<db::FlashCard>,
+}
+
+impl Report {
+    pub fn construct(conn: &Connection) -> Result<Self> {
+        Ok(Self {
+            total_cards: db::fetch_initial_flash_card_count(false, conn)?,
+            titles: db::construct_title_report(conn)?.report_lines,
+            authors: db::construct_author_report(conn)?,
+            added_per_month: db::construct_monthly_report(conn)?,
+            most_viewed: db::fetch_view_extremes(true, VIEW_EXTREMES, conn)?,
+            least_viewed: db::fetch_view_extremes(false, VIEW_EXTREMES, conn)?,
+            average_body_length: db::average_body_length(conn)?,
+            problem_cards: db::fetch_problem_cards(conn)?,
+            favourites: db::fetch_favourites(conn)?,
+        })
+    }
+}
+
+pub fn print_out_report(report: &Report, format: ReportFormat) -> Result<()> {
+    match format {
+        ReportFormat::Text => print_text_report(report),
+        ReportFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(report)?);
+            Ok(())
+        }
+        ReportFormat::Csv => write_csv_report(report, stdout()),
+    }
+}
+
+fn print_heading(heading: &str) {
+    println!();
+    println!("{}", heading.red());
+    println!("{}", "=".repeat(heading.len()).yellow());
+}
+
+fn print_text_report(report: &Report) -> Result<()> {
     println!("{}", "Report on titles".red());
     println!("{}", "=================".yellow());
-    title_report.report_lines.iter().for_each(|line| {
+    report.titles.iter().for_each(|line| {
         println!(
             "{} -> {}",
             line.title.trim().cyan(),
             line.title_count.to_string().purple()
         );
     });
+
+    print_heading("Report on authors");
+    report.authors.iter().for_each(|line| {
+        let author = if line.name.is_empty() {
+            "(unknown)"
+        } else {
+            line.name.trim()
+        };
+        println!("{} -> {}", author.cyan(), line.count.to_string().purple());
+    });
+
+    print_heading("Cards added per month");
+    report.added_per_month.iter().for_each(|line| {
+        println!(
+            "{} -> {}",
+            line.name.cyan(),
+            line.count.to_string().purple()
+        );
+    });
+
+    print_heading("Most viewed");
+    report.most_viewed.iter().for_each(print_card_views);
+    print_heading("Least viewed");
+    report.least_viewed.iter().for_each(print_card_views);
+
+    print_heading("Bodies");
+    println!(
+        "{} cards, average body length {} characters",
+        report.total_cards.to_string().purple(),
+        format!("{:.1}", report.average_body_length).purple()
+    );
+    report.problem_cards.iter().for_each(|card| {
+        println!(
+            "#{} {} -> {}",
+            card.id,
+            card.title.trim().cyan(),
+            card.problem.red()
+        );
+    });
+
+    print_heading("Favourites");
+    if report.favourites.is_empty() {
+        println!("{}", "No favourites yet, star a card with *".cyan());
+    }
+    report.favourites.iter().for_each(|fc| {
+        println!(
+            "{} {}",
+            "★".yellow(),
+            fc.title.trim().replace('\n', " - ").cyan()
+        );
+        fc.body
+            .trim()
+            .lines()
+            .for_each(|line| println!("    {}", line.trim()));
+    });
     Ok(())
 }
+
+fn print_card_views(card: &db::CardViews) {
+    println!(
+        "#{} {} -> {}",
+        card.id,
+        card.title.trim().cyan(),
+        card.view_count.to_string().purple()
+    );
+}
+
+///One flat section,name,value table so the sections can share a file
+fn write_csv_report(report: &Report, out: impl Write) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(["section", "name", "value"])?;
+    writer.write_record(["total_cards", "", &report.total_cards.to_string()])?;
+    for line in &report.titles {
+        writer.write_record(["title", line.title.trim(), &line.title_count.to_string()])?;
+    }
+    for line in &report.authors {
+        writer.write_record(["author", line.name.trim(), &line.count.to_string()])?;
+    }
+    for line in &report.added_per_month {
+        writer.write_record(["added_per_month", &line.name, &line.count.to_string()])?;
+    }
+    for (section, cards) in [
+        ("most_viewed", &report.most_viewed),
+        ("least_viewed", &report.least_viewed),
+    ] {
+        for card in cards {
+            writer.write_record([
+                section,
+                &format!("#{} {}", card.id, card.title.trim()),
+                &card.view_count.to_string(),
+            ])?;
+        }
+    }
+    writer.write_record([
+        "average_body_length",
+        "",
+        &format!("{:.1}", report.average_body_length),
+    ])?;
+    for card in &report.problem_cards {
+        writer.write_record([
+            "problem_card",
+            &format!("#{} {}", card.id, card.title.trim()),
+            &card.problem,
+        ])?;
+    }
+    for fc in &report.favourites {
+        writer.write_record(["favourite", &format!("#{} {}", fc.id, fc.title.trim()), ""])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    pub fn test_json_and_csv_reports() {
+        let conn = Connection::open_in_memory().unwrap();
+        db::init_table(&conn).unwrap();
+        for (title, author, body) in [
+            ("Meditations", "Aurelius, Marcus", "Waste no more time"),
+            (
+                "Meditations",
+                "Aurelius, Marcus",
+                "You have power over your mind",
+            ),
+            ("Walden", "Thoreau", "Simplify, simplify"),
+        ] {
+            let fc = db::FlashCard {
+                title: title.to_string(),
+                source_title: title.to_string(),
+                author: author.to_string(),
+                body: body.to_string(),
+                ..Default::default()
+            };
+            db::save_flashcard_object(&fc, &conn).unwrap();
+        }
+        let report = Report::construct(&conn).unwrap();
+
+        let json = serde_json::to_value(&report).unwrap();
+        let keys = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "added_per_month",
+                "authors",
+                "average_body_length",
+                "favourites",
+                "least_viewed",
+                "most_viewed",
+                "problem_cards",
+                "titles",
+                "total_cards"
+            ],
+            keys
+        );
+        assert_eq!(3, json["total_cards"]);
+
+        let mut csv = Vec::new();
+        write_csv_report(&report, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!("section,name,value", lines[0]);
+        assert_eq!("total_cards,,3", lines[1]);
+        assert!(lines.contains(&"title,Meditations,2"));
+        assert!(lines.contains(&"author,\"Aurelius, Marcus\",2"));
+        assert!(lines.contains(&"author,Thoreau,1"));
+    }
+}
@@ -0,0 +1,459 @@
+use std::io::Read;
+
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use log::{error, info};
+use rand::Rng;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Response, Server};
+
+use crate::bulk::parse_id_range;
+use crate::db::{self, CardSelection, FlashCard};
+use crate::import::percent_decode;
+use crate::report::Report;
+use crate::validate::check_flashcard;
+
+pub const DEFAULT_PORT: u16 = 7373;
+///Cards are small, anything bigger than this is turned away with a 413
+const MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+///A request boiled down to what the routes need, so they can be tested without a socket
+#[derive(Debug, Default)]
+pub struct ApiRequest {
+    pub method: String,
+    ///Path and query string, eg `/cards?q=stoic`
+    pub url: String,
+    ///What came after `Bearer` in the Authorization header
+    pub token: Option<String>,
+    pub host: Option<String>,
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl ApiResponse {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message }),
+        }
+    }
+}
+
+///Serve the JSON API on localhost until killed, one request at a time.
+///With a token, every request has to carry `Authorization: Bearer <token>`.
+///Requests for any other Host are turned away, so a web page can't reach the
+///API by pointing its own domain at 127.0.0.1
+pub fn serve(port: u16, token: Option<String>, conn: &Connection) -> Result<()> {
+    let server = Server::http(("127.0.0.1", port)).map_err(|e| anyhow!(e))?;
+    println!(
+        "Serving flashcards on {}, Ctrl+C to stop",
+        format!("http://127.0.0.1:{}", port).cyan()
+    );
+    let json_header = Header::from_bytes("Content-Type", "application/json")
+        .map_err(|_| anyhow!("bad content type header"))?;
+    for mut request in server.incoming_requests() {
+        let response = match read_body(request.as_reader()) {
+            Ok(body) => {
+                let header = |name: &'static str| {
+                    request
+                        .headers()
+                        .iter()
+                        .find(|header| header.field.equiv(name))
+                        .map(|header| header.value.as_str().trim().to_string())
+                };
+                let api_request = ApiRequest {
+                    method: request.method().to_string(),
+                    url: request.url().to_string(),
+                    token: header("Authorization")
+                        .and_then(|value| Some(value.strip_prefix("Bearer ")?.trim().to_string())),
+                    host: header("Host"),
+                    content_type: header("Content-Type"),
+                    body,
+                };
+                handle_request(&api_request, port, token.as_deref(), conn)
+            }
+            Err(response) => response,
+        };
+        info!(
+            "{} {} -> {}",
+            request.method(),
+            request.url(),
+            response.status
+        );
+        let reply = Response::from_string(response.body.to_string())
+            .with_status_code(response.status)
+            .with_header(json_header.clone());
+        if let Err(e) = request.respond(reply) {
+            error!("Couldn't answer a request: {}", e);
+        }
+    }
+    Ok(())
+}
+
+///The request body as text, no more than MAX_BODY_BYTES of it
+fn read_body(reader: impl Read) -> std::result::Result<String, ApiResponse> {
+    let mut body = Vec::new();
+    if reader
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_end(&mut body)
+        .is_err()
+    {
+        return Err(ApiResponse::error(400, "couldn't read the body"));
+    }
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(ApiResponse::error(413, "body is too large"));
+    }
+    String::from_utf8(body).map_err(|_| ApiResponse::error(400, "body is not UTF-8 text"))
+}
+
+///Check the host, token and content type then route. Anything that goes wrong
+///in the database is logged here and the client just gets a 500
+pub fn handle_request(
+    request: &ApiRequest,
+    port: u16,
+    token: Option<&str>,
+    conn: &Connection,
+) -> ApiResponse {
+    let local_hosts = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    if !request.host.as_ref().is_some_and(|host| {
+        local_hosts
+            .iter()
+            .any(|local| local.eq_ignore_ascii_case(host))
+    }) {
+        return ApiResponse::error(403, "only requests for localhost are served");
+    }
+    if token.is_some_and(|token| !same_token(request.token.as_deref().unwrap_or_default(), token)) {
+        return ApiResponse::error(401, "missing or wrong token");
+    }
+    let is_json = request.content_type.as_deref().is_some_and(|content_type| {
+        let media_type = content_type.split(';').next().unwrap_or_default();
+        media_type.trim().eq_ignore_ascii_case("application/json")
+    });
+    if matches!(request.method.as_str(), "POST" | "PUT") && !is_json {
+        return ApiResponse::error(415, "send cards as application/json");
+    }
+    route(request, conn).unwrap_or_else(|e| {
+        error!("{} {} failed: {}", request.method, request.url, e);
+        ApiResponse::error(500, "internal error")
+    })
+}
+
+///Compare tokens in constant time, by way of their hashes so the length doesn't show either
+fn same_token(given: &str, expected: &str) -> bool {
+    let (given, expected) = (Sha256::digest(given), Sha256::digest(expected));
+    given
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+///```text
+///GET    /cards?q=&title=&ids=   list, or search with any of the filters
+///POST   /cards                  create from a JSON card
+///GET    /cards/random           a random card, ?favourites=true for starred ones
+///GET    /cards/<id>
+///PUT    /cards/<id>             change the fields given, the rest stay as they are
+///DELETE /cards/<id>
+///GET    /report
+///```
+fn route(request: &ApiRequest, conn: &Connection) -> Result<ApiResponse> {
+    let (path, query) = request.url.split_once('?').unwrap_or((&request.url, ""));
+    let params = query_params(query);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    };
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["cards"]) => {
+            let ids = match param("ids").map(|ids| parse_id_range(&ids)).transpose() {
+                Ok(ids) => ids,
+                Err(e) => return Ok(ApiResponse::error(400, &e)),
+            };
+            let selection = CardSelection {
+                query: param("q"),
+                title: param("title"),
+                ids,
+            };
+            let cards = db::find_flashcards(&selection, conn)?;
+            Ok(ApiResponse::ok(serde_json::to_value(cards)?))
+        }
+        ("POST", ["cards"]) => {
+            let fc = match serde_json::from_str::<FlashCard>(&request.body) {
+                Ok(fc) => fc,
+                Err(e) => return Ok(ApiResponse::error(400, &format!("not a card, {}", e))),
+            };
+            let checked = check_flashcard(fc);
+            if !checked.is_valid() {
+                return Ok(ApiResponse::error(422, &checked.describe_problems()));
+            }
+            let id = db::save_flashcard_object(&checked.card, conn)?;
+            let created = db::fetch_flashcard(id, conn)?;
+            Ok(ApiResponse {
+                status: 201,
+                body: serde_json::to_value(created)?,
+            })
+        }
+        ("GET", ["cards", "random"]) => {
+            let favourites_only = param("favourites").is_some_and(|value| value == "true");
+            let count = db::fetch_initial_flash_card_count(favourites_only, conn)?;
+            if count == 0 {
+                return Ok(ApiResponse::error(404, "no flashcards"));
+            }
+            let offset = rand::thread_rng().gen_range(0..count);
+            let fc = db::next_flashcard(offset, favourites_only, conn)?;
+            Ok(ApiResponse::ok(serde_json::to_value(fc)?))
+        }
+        (method, ["cards", id]) => {
+            let Ok(id) = id.parse::<usize>() else {
+                return Ok(ApiResponse::error(404, "no such card"));
+            };
+            let Some(fc) = db::fetch_flashcard(id, conn)? else {
+                return Ok(ApiResponse::error(404, "no such card"));
+            };
+            match method {
+                "GET" => Ok(ApiResponse::ok(serde_json::to_value(fc)?)),
+                "PUT" => update_card(fc, &request.body, conn),
+                "DELETE" => {
+                    db::delete_flashcard(id, conn)?;
+                    Ok(ApiResponse::ok(json!({ "deleted": id })))
+                }
+                _ => Ok(ApiResponse::error(405, "cards can be got, put or deleted")),
+            }
+        }
+        ("GET", ["report"]) => {
+            let report = Report::construct(conn)?;
+            Ok(ApiResponse::ok(serde_json::to_value(report)?))
+        }
+        _ => Ok(ApiResponse::error(404, "no such endpoint")),
+    }
+}
+
+///Lay the fields in the body over the card as it is, so a browser extension can
+///send just `{"favourite": true}`
+fn update_card(fc: FlashCard, body: &str, conn: &Connection) -> Result<ApiResponse> {
+    let changes = match serde_json::from_str::<Value>(body) {
+        Ok(Value::Object(changes)) => changes,
+        _ => return Ok(ApiResponse::error(400, "expected a JSON object of fields")),
+    };
+    let id = fc.id;
+    let mut merged = serde_json::to_value(fc)?;
+    if let Value::Object(fields) = &mut merged {
+        fields.extend(changes);
+    }
+    let fc = match serde_json::from_value::<FlashCard>(merged) {
+        Ok(fc) => fc,
+        Err(e) => return Ok(ApiResponse::error(400, &format!("not a card, {}", e))),
+    };
+    let checked = check_flashcard(FlashCard { id, ..fc });
+    if !checked.is_valid() {
+        return Ok(ApiResponse::error(422, &checked.describe_problems()));
+    }
+    db::update_flashcard(&checked.card, conn)?;
+    let updated = db::fetch_flashcard(id, conn)?;
+    Ok(ApiResponse::ok(serde_json::to_value(updated)?))
+}
+
+///`a=1&b=two+words` into pairs, decoded
+fn query_params(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |text: &str| percent_decode(&text.replace('+', " "));
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn call(method: &str, url: &str, body: &str, conn: &Connection) -> ApiResponse {
+        let request = ApiRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            host: Some(format!("localhost:{}", DEFAULT_PORT)),
+            content_type: Some("application/json".to_string()),
+            body: body.to_string(),
+            ..Default::default()
+        };
+        handle_request(&request, DEFAULT_PORT, None, conn)
+    }
+
+    #[test]
+    pub fn test_card_endpoints() {
+        let conn = Connection::open_in_memory().unwrap();
+        db::init_table(&conn).unwrap();
+
+        let created = call(
+            "POST",
+            "/cards",
+            r#"{"title": "Meditations", "body": "Waste no more time", "author": "Marcus Aurelius"}"#,
+            &conn,
+        );
+        assert_eq!(201, created.status);
+        let id = created.body["id"].as_u64().unwrap();
+        let starred = call(
+            "POST",
+            "/cards",
+            r#"{"title": "Letters", "body": "Hurry", "favourite": true}"#,
+            &conn,
+        );
+        assert_eq!(true, starred.body["favourite"]);
+        assert_eq!(
+            422,
+            call("POST", "/cards", r#"{"title": "Empty"}"#, &conn).status
+        );
+        assert_eq!(400, call("POST", "/cards", "not json", &conn).status);
+
+        let found = call("GET", "/cards?q=no+more%20time", "", &conn);
+        assert_eq!(1, found.body.as_array().unwrap().len());
+        assert_eq!(
+            2,
+            call("GET", "/cards", "", &conn)
+                .body
+                .as_array()
+                .unwrap()
+                .len()
+        );
+        assert_eq!(400, call("GET", "/cards?ids=9-1", "", &conn).status);
+
+        let updated = call(
+            "PUT",
+            &format!("/cards/{}", id),
+            r#"{"favourite": true}"#,
+            &conn,
+        );
+        assert_eq!(200, updated.status);
+        assert_eq!(true, updated.body["favourite"]);
+        assert_eq!("Waste no more time", updated.body["body"]);
+        let random = call("GET", "/cards/random?favourites=true", "", &conn);
+        assert_eq!(true, random.body["favourite"]);
+
+        assert_eq!(2, call("GET", "/report", "", &conn).body["total_cards"]);
+        assert_eq!(
+            200,
+            call("DELETE", &format!("/cards/{}", id), "", &conn).status
+        );
+        assert_eq!(
+            404,
+            call("GET", &format!("/cards/{}", id), "", &conn).status
+        );
+        assert_eq!(404, call("GET", "/nowhere", "", &conn).status);
+    }
+
+    #[test]
+    pub fn test_token_is_required() {
+        let conn = Connection::open_in_memory().unwrap();
+        db::init_table(&conn).unwrap();
+        let mut request = ApiRequest {
+            method: "GET".to_string(),
+            url: "/cards".to_string(),
+            host: Some(format!("127.0.0.1:{}", DEFAULT_PORT)),
+            ..Default::default()
+        };
+        let status = |request: &ApiRequest| {
+            handle_request(request, DEFAULT_PORT, Some("sesame"), &conn).status
+        };
+        assert_eq!(401, status(&request));
+        request.token = Some("sesam".to_string());
+        assert_eq!(401, status(&request));
+        request.token = Some("sesame".to_string());
+        assert_eq!(200, status(&request));
+    }
+
+    #[test]
+    pub fn test_other_hosts_are_refused() {
+        let conn = Connection::open_in_memory().unwrap();
+        db::init_table(&conn).unwrap();
+        let mut request = ApiRequest {
+            method: "GET".to_string(),
+            url: "/cards".to_string(),
+            host: Some(format!("evil.example:{}", DEFAULT_PORT)),
+            ..Default::default()
+        };
+        assert_eq!(
+            403,
+            handle_request(&request, DEFAULT_PORT, None, &conn).status
+        );
+        request.host = Some("localhost:8080".to_string());
+        assert_eq!(
+            403,
+            handle_request(&request, DEFAULT_PORT, None, &conn).status
+        );
+        request.host = None;
+        assert_eq!(
+            403,
+            handle_request(&request, DEFAULT_PORT, None, &conn).status
+        );
+    }
+
+    #[test]
+    pub fn test_cards_must_be_sent_as_json() {
+        let conn = Connection::open_in_memory().unwrap();
+        db::init_table(&conn).unwrap();
+        let mut request = ApiRequest {
+            method: "POST".to_string(),
+            url: "/cards".to_string(),
+            host: Some(format!("localhost:{}", DEFAULT_PORT)),
+            content_type: Some("text/plain".to_string()),
+            body: r#"{"title": "Letters", "body": "Hurry"}"#.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            415,
+            handle_request(&request, DEFAULT_PORT, None, &conn).status
+        );
+        request.content_type = None;
+        assert_eq!(
+            415,
+            handle_request(&request, DEFAULT_PORT, None, &conn).status
+        );
+        request.content_type = Some("application/json; charset=utf-8".to_string());
+        assert_eq!(
+            201,
+            handle_request(&request, DEFAULT_PORT, None, &conn).status
+        );
+    }
+
+    #[test]
+    pub fn test_errors_and_large_bodies() {
+        let conn = Connection::open_in_memory().unwrap();
+        db::init_table(&conn).unwrap();
+        conn.execute("DROP TABLE flashcard", []).unwrap();
+        let failed = call("GET", "/cards", "", &conn);
+        assert_eq!(500, failed.status);
+        assert_eq!(json!({ "error": "internal error" }), failed.body);
+
+        assert_eq!(
+            Ok("{}".to_string()),
+            read_body("{}".as_bytes()).map_err(|_| ())
+        );
+        let large = vec![b' '; MAX_BODY_BYTES as usize + 1];
+        assert_eq!(413, read_body(large.as_slice()).unwrap_err().status);
+        assert_eq!(400, read_body([0xff, 0xfe].as_slice()).unwrap_err().status);
+    }
+}
@@ -1,13 +1,17 @@
 use std::thread;
 use std::time::Duration;
 
-use crate::app::{App, State};
+use crate::app::{App, ListPrompt, State};
 use anyhow::Result;
 use log::info;
-use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use ratatui::prelude::{Color, Margin, Style};
 use ratatui::style::Modifier;
-use ratatui::widgets::{Block, Borders, Paragraph, Scrollbar, ScrollbarOrientation, Wrap};
+use ratatui::text::Line;
+use ratatui::widgets::{
+    Bar, BarChart, BarGroup, Block, Borders, Gauge, List, ListItem, Paragraph, Scrollbar,
+    ScrollbarOrientation, Sparkline, Wrap,
+};
 use ratatui::Frame;
 
 pub fn render_app(frame: &mut Frame, app: &mut App) {
@@ -21,9 +25,16 @@ pub fn render_app(frame: &mut Frame, app: &mut App) {
     //render the top message
     let top_text = match app.state {
         State::AddFlashcard => "Ctrl+s to save, Ctrl+b to go back",
+        State::ListFlashcards | State::ListPrompt | State::DisplayBulkDeletePopup => {
+            "[Space] select | [*] all | [T]itle | [/] search | [B]ack
+            [D]elete | Ta[G] | [M]ove to deck | E[X]port | [Q]uit"
+        }
+        State::ShowStats => "Study statistics - [B]ack | [Q]uit",
         _ => {
             "Welcome to Rashcard, the Rust Flashcard application
-            [N]ext | [R]andom | [P]revious | [A]dd | [D]elete | Cop[Y] | [Q]uit"
+            [N]ext | [R]andom | [P]revious | [A]dd | Past[E] new card | [D]elete | Cop[Y] | [L]ist | [Q]uit
+            [T]imer on/off | [S]top/start timer | [+/-] timer interval | [M]ode | Reading time [W] | [*] star | Fa[V]ourites only
+            [C]orrect | [X] wrong | [G]raphs"
         }
     };
 
@@ -45,24 +56,83 @@ pub fn render_app(frame: &mut Frame, app: &mut App) {
         State::Idling => draw_placeholder(frame, main_display),
         State::ShowFlashcard => display_current_flashcard(frame, main_display, app),
         State::AddFlashcard => display_add_flashcard(frame, main_display, app),
-        State::DisplaySavedPopup => {
+        State::DisplayMessagePopup => {
             // info!("Saved! About to display the same");
-            draw_saved_popup(frame).unwrap();
-            app.close_popup_if_it_is_time(500);
+            display_popup(&app.popup_message, frame).unwrap();
+            app.close_popup_if_it_is_time(app.popup_millis);
         }
         State::DisplayDeletePopup => draw_delete_popup(frame).unwrap(),
+        State::ListFlashcards => display_card_list(frame, main_display, app),
+        State::ListPrompt => {
+            let parts =
+                Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).split(main_display);
+            display_card_list(frame, parts[0], app);
+            display_list_prompt(frame, parts[1], app);
+        }
+        State::DisplayBulkDeletePopup => {
+            display_card_list(frame, main_display, app);
+            draw_bulk_delete_popup(frame, app.selected_cards.len()).unwrap();
+        }
+        State::ShowStats => display_stats(frame, main_display, app),
     }
 
     //down at the SIDE-BAR, SIDE-BAR, SIDE-BAR!!
     let side_bar = cols[1];
 
+    let timer_text = if app.timer.enabled && app.timer.reading_time {
+        format!(
+            "{}s (reading @ {}wpm)",
+            app.timer.card_cycle, app.timer.reading_pace.words_per_minute
+        )
+    } else if app.timer.enabled {
+        format!("{}s", app.timer.next_card_cycle)
+    } else {
+        "off".to_string()
+    };
     let message_text = format!(
         r"Total Cards: {}
 This is card #{}
-Cards displayed: {}",
-        app.total_cards, app.current_flashcard_number, app.cards_displayed
+Cards displayed: {}
+Timer: {}
+Mode: {:?}
+Studying: {}",
+        app.total_cards,
+        app.current_flashcard_number,
+        app.cards_displayed,
+        timer_text,
+        app.draw_mode,
+        if app.favourites_only {
+            "favourites"
+        } else {
+            "all cards"
+        }
     );
-    draw_sidebar(&message_text, frame, side_bar)
+    if app.timer.enabled {
+        let parts = Layout::vertical([Constraint::Min(8), Constraint::Length(3)]).split(side_bar);
+        draw_sidebar(&message_text, frame, parts[0]);
+        draw_timer_gauge(frame, parts[1], app);
+    } else {
+        draw_sidebar(&message_text, frame, side_bar)
+    }
+}
+
+///Countdown to the next card
+fn draw_timer_gauge(frame: &mut Frame, rect: Rect, app: &App) {
+    let label = if app.timer.is_paused() {
+        "paused".to_string()
+    } else {
+        format!("{}s", app.timer.remaining().as_secs())
+    };
+    let gauge = Gauge::default()
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::LightBlue)),
+        )
+        .gauge_style(Style::default().fg(Color::Yellow))
+        .ratio(app.timer.progress())
+        .label(label);
+    frame.render_widget(gauge, rect);
 }
 
 fn draw_sidebar(txt: &str, frame: &mut Frame, rect: Rect) {
@@ -88,10 +158,6 @@ fn draw_placeholder(frame: &mut Frame, rect: Rect) {
     frame.render_widget(msg, rect);
 }
 
-fn draw_saved_popup(f: &mut Frame) -> Result<()> {
-    display_popup("Saved", f)
-}
-
 fn draw_delete_popup(f: &mut Frame) -> Result<()> {
     let txt = r"Really delete this flashcard?
                [Y]es | [N]o";
@@ -106,12 +172,23 @@ fn draw_delete_popup(f: &mut Frame) -> Result<()> {
     Ok(())
 }
 
-fn display_popup(msg: &str, f: &mut Frame) -> anyhow::Result<()> {
-    let msg = Paragraph::new(msg).block(
-        Block::default()
-            .borders(Borders::ALL)
-            .style(Style::default().fg(Color::DarkGray)),
+fn draw_bulk_delete_popup(f: &mut Frame, count: usize) -> Result<()> {
+    let txt = format!(
+        r"Really delete {} flashcards?
+               [Y]es | [N]o",
+        count
     );
+    display_popup(&txt, f)
+}
+
+fn display_popup(msg: &str, f: &mut Frame) -> anyhow::Result<()> {
+    let msg = Paragraph::new(msg)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::DarkGray)),
+        )
+        .wrap(Wrap { trim: true });
 
     let rect = centered_rect(20, 20, f.area());
     f.render_widget(msg, rect);
@@ -144,12 +221,19 @@ fn display_current_flashcard(frame: &mut Frame, rect: Rect, app: &mut App) {
         app.current_flash_text.clone()
     };
 
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Cyan));
+    if app.current_flash_favourite {
+        block = block.title(Line::styled(" ★ ", Style::default().fg(Color::Yellow)));
+    }
+    //where it came from, unless we are still hiding the answer
+    if !app.current_flash_source.is_empty() && (!app.flip_mode || app.card_flipped) {
+        block = block.title_bottom(format!(" — {} ", app.current_flash_source));
+    }
+
     let msg = Paragraph::new(text.clone())
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .style(Style::default().fg(Color::Cyan)),
-        )
+        .block(block)
         .scroll((app.vertical_scroll as u16, 0))
         .wrap(Wrap { trim: false });
 
@@ -170,6 +254,133 @@ fn display_current_flashcard(frame: &mut Frame, rect: Rect, app: &mut App) {
     }
 }
 
+///List of cards, with the selected ones ticked
+fn display_card_list(frame: &mut Frame, rect: Rect, app: &mut App) {
+    let items = app
+        .card_list
+        .iter()
+        .map(|fc| {
+            let tick = if app.selected_cards.contains(&fc.id) {
+                "[x]"
+            } else {
+                "[ ]"
+            };
+            let title = fc.title.trim().replace('\n', " - ");
+            let body = fc.body.trim().lines().next().unwrap_or_default();
+            ListItem::new(format!("{} #{} {} | {}", tick, fc.id, title, body))
+        })
+        .collect::<Vec<_>>();
+
+    let mut block_title = format!(
+        " {} cards, {} selected ",
+        app.card_list.len(),
+        app.selected_cards.len()
+    );
+    if !app.list_query.is_empty() {
+        block_title.push_str(&format!("| search: {} ", app.list_query));
+    }
+    if !app.list_message.is_empty() {
+        block_title.push_str(&format!("| {} ", app.list_message));
+    }
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title(block_title)
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::Cyan)),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, rect, &mut app.list_state);
+}
+
+fn display_list_prompt(frame: &mut Frame, rect: Rect, app: &App) {
+    let label = match app.list_prompt {
+        ListPrompt::Search => "Search",
+        ListPrompt::Retag => "Tags (comma separated)",
+        ListPrompt::Move => "Deck",
+        ListPrompt::Export => "Export to file (.md, .csv) or directory/ for org",
+    };
+    let msg = Paragraph::new(app.prompt_input.as_str()).block(
+        Block::default()
+            .title(format!(" {} - Enter to apply, Esc to cancel ", label))
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::Yellow)),
+    );
+    frame.render_widget(msg, rect);
+}
+
+///How many titles fit in the distribution chart
+const STATS_TITLES: usize = 10;
+
+///Streak, accuracy, reviews per day and cards per title
+fn display_stats(frame: &mut Frame, rect: Rect, app: &App) {
+    let stats = &app.stats;
+    let parts = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Length(6),
+        Constraint::Min(5),
+    ])
+    .split(rect);
+
+    let accuracy = match stats.accuracy() {
+        Some(acc) => format!("{:.0}% of {} graded", acc * 100.0, stats.graded),
+        None => "nothing graded yet, [C]orrect or [X] wrong on a card".to_string(),
+    };
+    let summary = Paragraph::new(format!(
+        "Streak: {} days\nReviews: {}\nAccuracy: {}",
+        stats.streak, stats.total_reviews, accuracy
+    ))
+    .block(
+        Block::default()
+            .title(" Stats ")
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::Cyan)),
+    );
+    frame.render_widget(summary, parts[0]);
+
+    let per_day = stats
+        .reviews_per_day
+        .iter()
+        .map(|(_, count)| *count)
+        .collect::<Vec<_>>();
+    let first_day = stats
+        .reviews_per_day
+        .first()
+        .map(|(day, _)| day.as_str())
+        .unwrap_or_default();
+    let sparkline = Sparkline::default()
+        .block(
+            Block::default()
+                .title(format!(" Reviews per day since {} ", first_day))
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::LightBlue)),
+        )
+        .data(&per_day)
+        .style(Style::default().fg(Color::Yellow));
+    frame.render_widget(sparkline, parts[1]);
+
+    let bars = stats
+        .cards_per_title
+        .iter()
+        .take(STATS_TITLES)
+        .map(|(title, count)| Bar::default().label(title.as_str().into()).value(*count))
+        .collect::<Vec<_>>();
+    let chart = BarChart::default()
+        .block(
+            Block::default()
+                .title(" Cards by title ")
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::LightBlue)),
+        )
+        .direction(Direction::Horizontal)
+        .bar_width(1)
+        .bar_gap(0)
+        .bar_style(Style::default().fg(Color::Yellow))
+        .data(BarGroup::default().bars(&bars));
+    frame.render_widget(chart, parts[2]);
+}
+
 ///Create a 'centered' rect using percentage
 fn centered_rect(h: u16, v: u16, rect: Rect) -> Rect {
     //cut into 3 vertical rows
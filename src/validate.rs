@@ -0,0 +1,145 @@
+use std::fmt;
+
+use crate::db::FlashCard;
+
+///Bodies longer than this still get saved, but we grumble about it
+pub const MAX_BODY_CHARS: usize = 2000;
+
+///Something wrong with a card, see CardProblem::is_error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CardProblem {
+    EmptyBody,
+    EmptyTitle,
+    BodyTooLong(usize),
+}
+
+impl CardProblem {
+    ///Errors stop a card from being saved, everything else is a warning
+    pub fn is_error(&self) -> bool {
+        matches!(self, CardProblem::EmptyBody)
+    }
+}
+
+impl fmt::Display for CardProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CardProblem::EmptyBody => write!(f, "empty body"),
+            CardProblem::EmptyTitle => write!(f, "no title"),
+            CardProblem::BodyTooLong(len) => write!(
+                f,
+                "body is {} characters, more than {}",
+                len, MAX_BODY_CHARS
+            ),
+        }
+    }
+}
+
+///A cleaned up card along with whatever is still wrong with it
+#[derive(Debug)]
+pub struct CheckedCard {
+    pub card: FlashCard,
+    pub problems: Vec<CardProblem>,
+}
+
+impl CheckedCard {
+    pub fn is_valid(&self) -> bool {
+        !self.problems.iter().any(CardProblem::is_error)
+    }
+
+    ///All the problems on one line, for reporting
+    pub fn describe_problems(&self) -> String {
+        self.problems
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+///Unix line endings, no trailing whitespace on lines, nothing blank at either end
+pub fn normalise_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+///Clean the card up and work out what, if anything, is wrong with it.
+///Used by the editor and every importer before anything is saved
+pub fn check_flashcard(mut card: FlashCard) -> CheckedCard {
+    card.title = normalise_text(&card.title);
+    card.body = normalise_text(&card.body);
+    card.note = normalise_text(&card.note);
+
+    let mut problems = Vec::new();
+    if card.body.is_empty() {
+        problems.push(CardProblem::EmptyBody);
+    }
+    if card.title.is_empty() {
+        problems.push(CardProblem::EmptyTitle);
+    }
+    let len = card.body.chars().count();
+    if len > MAX_BODY_CHARS {
+        problems.push(CardProblem::BodyTooLong(len));
+    }
+    CheckedCard { card, problems }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn card(title: &str, body: &str) -> FlashCard {
+        FlashCard {
+            title: title.to_string(),
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_normalise_text() {
+        assert_eq!(
+            "one\ntwo\nthree",
+            normalise_text("\r\n one  \r\ntwo\rthree\n\n")
+        );
+        assert_eq!("", normalise_text(" \n\t\r\n"));
+    }
+
+    #[test]
+    pub fn test_empty_body_is_rejected() {
+        let checked = check_flashcard(card("Title", "  \r\n  "));
+        assert!(!checked.is_valid());
+        assert_eq!(vec![CardProblem::EmptyBody], checked.problems);
+    }
+
+    #[test]
+    pub fn test_missing_title_is_only_a_warning() {
+        let checked = check_flashcard(card(" ", "body"));
+        assert!(checked.is_valid());
+        assert_eq!(vec![CardProblem::EmptyTitle], checked.problems);
+    }
+
+    #[test]
+    pub fn test_long_body_is_only_a_warning() {
+        let checked = check_flashcard(card("Title", &"x".repeat(MAX_BODY_CHARS + 1)));
+        assert!(checked.is_valid());
+        assert_eq!(
+            vec![CardProblem::BodyTooLong(MAX_BODY_CHARS + 1)],
+            checked.problems
+        );
+    }
+
+    #[test]
+    pub fn test_good_card_is_cleaned_up() {
+        let checked = check_flashcard(card("Title\r\n", " line one \r\nline two  "));
+        assert!(checked.problems.is_empty());
+        assert_eq!("Title", checked.card.title);
+        assert_eq!("line one\nline two", checked.card.body);
+    }
+}